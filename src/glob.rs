@@ -0,0 +1,112 @@
+use crate::ignore::{parse_rule, Rule};
+use crate::ConfigError;
+use std::path::Path;
+
+/// The `--glob` and `--iglob` patterns. They use the same syntax as
+/// `.gitignore` files, but a plain pattern selects files and a `!pattern`
+/// excludes them. As with ignore files, the last matching pattern wins.
+#[derive(Default)]
+pub struct GlobFilter {
+    rules: Vec<Rule>,
+    has_includes: bool,
+}
+
+impl GlobFilter {
+    pub fn add(&mut self, pattern: &str, case_insensitive: bool) -> Result<(), ConfigError> {
+        match parse_rule(pattern, case_insensitive) {
+            Ok(Some(rule)) => {
+                if !rule.negated {
+                    self.has_includes = true;
+                }
+                self.rules.push(rule);
+                Ok(())
+            }
+            Ok(None) => Err(ConfigError::InvalidGlob(
+                pattern.to_string(),
+                "empty pattern".to_string(),
+            )),
+            Err(err) => Err(ConfigError::InvalidGlob(pattern.to_string(), err)),
+        }
+    }
+
+    /// `path` has to be relative to the searched folder. Folders are only
+    /// ever excluded by a `!pattern`, so that the files inside them still get
+    /// a chance to be selected. A pattern selecting a folder selects all the
+    /// files below it, like `-g 'sub/'`.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        let in_selected_folder = |rule: &Rule| {
+            !is_dir
+                && !rule.negated
+                && path
+                    .ancestors()
+                    .skip(1)
+                    .take_while(|folder| !folder.as_os_str().is_empty())
+                    .any(|folder| rule.is_match(folder, true))
+        };
+        match self
+            .rules
+            .iter()
+            .rev()
+            .find(|rule| rule.is_match(path, is_dir) || in_selected_folder(rule))
+        {
+            Some(rule) => rule.negated,
+            None => !is_dir && self.has_includes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(patterns: &[&str]) -> GlobFilter {
+        let mut filter = GlobFilter::default();
+        for pattern in patterns {
+            filter.add(pattern, false).unwrap();
+        }
+        filter
+    }
+
+    fn excluded(filter: &GlobFilter, path: &str, is_dir: bool) -> bool {
+        filter.is_excluded(Path::new(path), is_dir)
+    }
+
+    #[test]
+    fn includes_select_files_and_never_exclude_folders() {
+        let filter = filter(&["*.rs"]);
+        assert!(!excluded(&filter, "src/main.rs", false));
+        assert!(excluded(&filter, "README.md", false));
+        assert!(!excluded(&filter, "src", true));
+    }
+
+    #[test]
+    fn the_last_matching_glob_wins() {
+        let filter = filter(&["*.rs", "!gen_*.rs", "gen_keep.rs"]);
+        assert!(!excluded(&filter, "a.rs", false));
+        assert!(excluded(&filter, "gen_a.rs", false));
+        assert!(!excluded(&filter, "gen_keep.rs", false));
+    }
+
+    #[test]
+    fn a_selected_folder_selects_the_files_below_it() {
+        let filter = filter(&["sub/", "!*.md"]);
+        assert!(!excluded(&filter, "sub/a.txt", false));
+        assert!(!excluded(&filter, "sub/deep/b.txt", false));
+        assert!(excluded(&filter, "sub/c.md", false));
+        assert!(excluded(&filter, "other/a.txt", false));
+        assert!(excluded(&filter, "sub", false));
+    }
+
+    #[test]
+    fn only_negated_globs_exclude_folders() {
+        let filter = filter(&["!target/"]);
+        assert!(excluded(&filter, "target", true));
+        assert!(!excluded(&filter, "target", false));
+        assert!(!excluded(&filter, "src/main.rs", false));
+    }
+
+    #[test]
+    fn an_empty_glob_is_an_error() {
+        assert!(GlobFilter::default().add("!", false).is_err());
+    }
+}
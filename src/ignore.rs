@@ -0,0 +1,388 @@
+use crate::ConfigError;
+use globset::{GlobBuilder, GlobMatcher};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Name of the ignore file only this tool reads.
+const RGREP_IGNORE_FILE: &str = ".rgrepignore";
+
+/// Which layers of ignore files are honored. All of them are on by default.
+pub struct IgnoreOptions {
+    /// `.gitignore` files, and skipping `.git` folders.
+    pub vcs: bool,
+    /// `.git/info/exclude` of a repository.
+    pub exclude: bool,
+    /// The user's global git ignore file.
+    pub global: bool,
+    /// `.ignore` files.
+    pub dot: bool,
+    /// `.rgrepignore` files.
+    pub rgrep: bool,
+}
+
+impl IgnoreOptions {
+    pub fn all() -> IgnoreOptions {
+        IgnoreOptions {
+            vcs: true,
+            exclude: true,
+            global: true,
+            dot: true,
+            rgrep: true,
+        }
+    }
+
+    pub fn none() -> IgnoreOptions {
+        IgnoreOptions {
+            vcs: false,
+            exclude: false,
+            global: false,
+            dot: false,
+            rgrep: false,
+        }
+    }
+}
+
+pub struct Rule {
+    glob: GlobMatcher,
+    pub negated: bool,
+    dir_only: bool,
+}
+
+impl Rule {
+    /// `path` has to be relative to the folder the rule applies to.
+    pub fn is_match(&self, path: &Path, is_dir: bool) -> bool {
+        (is_dir || !self.dir_only) && self.glob.is_match(path)
+    }
+}
+
+/// The rules of one ignore file. Paths are matched relative to `dir`, the
+/// folder the rules apply to.
+struct IgnoreFile {
+    dir: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+    /// Reads an ignore file. A missing file gives `None`, a pattern that
+    /// can't be parsed is skipped and reported in `warnings`.
+    fn read(file: &Path, dir: &Path, warnings: &mut Vec<ConfigError>) -> Option<IgnoreFile> {
+        let content = fs::read(file).ok()?;
+        let content = String::from_utf8_lossy(&content);
+
+        let mut rules = Vec::new();
+        for line in content.lines() {
+            match parse_rule(line, false) {
+                Ok(Some(rule)) => rules.push(rule),
+                Ok(None) => {}
+                Err(err) => warnings.push(ConfigError::InvalidIgnorePattern(
+                    file.to_string_lossy().into_owned(),
+                    line.to_string(),
+                    err,
+                )),
+            }
+        }
+
+        if rules.is_empty() {
+            return None;
+        }
+        Some(IgnoreFile {
+            dir: dir.to_path_buf(),
+            rules,
+        })
+    }
+
+    /// The last rule matching the path decides: `Some(true)` if it's ignored,
+    /// `Some(false)` if a negated rule whitelists it, `None` if nothing matched.
+    fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.dir).ok()?;
+
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.is_match(relative, is_dir))
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// Turns one line of a gitignore-style file into a rule, following git's
+/// rules: `#` starts a comment, `!` negates, a trailing `/` only matches
+/// folders and a `/` anywhere else anchors the pattern to the file's folder.
+pub fn parse_rule(line: &str, case_insensitive: bool) -> Result<Option<Rule>, String> {
+    if line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let mut pattern = trim_trailing_spaces(line);
+    if pattern.is_empty() {
+        return Ok(None);
+    }
+
+    let mut negated = false;
+    if let Some(rest) = pattern.strip_prefix('!') {
+        negated = true;
+        pattern = rest;
+    } else if pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+        pattern = &pattern[1..];
+    }
+
+    let mut dir_only = false;
+    if let Some(rest) = pattern.strip_suffix('/') {
+        dir_only = true;
+        pattern = rest;
+    }
+    if pattern.is_empty() {
+        return Ok(None);
+    }
+
+    let glob = if let Some(rest) = pattern.strip_prefix('/') {
+        rest.to_string()
+    } else if pattern.contains('/') {
+        pattern.to_string()
+    } else {
+        format!("**/{}", pattern)
+    };
+
+    let glob = GlobBuilder::new(&glob)
+        .literal_separator(true)
+        .backslash_escape(true)
+        .case_insensitive(case_insensitive)
+        .build()
+        .map_err(|err| err.kind().to_string())?
+        .compile_matcher();
+
+    Ok(Some(Rule {
+        glob,
+        negated,
+        dir_only,
+    }))
+}
+
+/// Trailing spaces are dropped unless they're escaped with a backslash.
+fn trim_trailing_spaces(line: &str) -> &str {
+    let trimmed = line.trim_end_matches([' ', '\t', '\r']);
+    if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        &line[..trimmed.len() + 1]
+    } else {
+        trimmed
+    }
+}
+
+/// The ignore files that apply to a folder: the ones found in it, then the
+/// ones of its parent folder and so on, up to the searched folder.
+pub struct IgnoreStack {
+    files: Vec<IgnoreFile>,
+    skip_git_dir: bool,
+    parent: Option<Arc<IgnoreStack>>,
+}
+
+impl IgnoreStack {
+    /// The stack for the searched folder, including the global ignore file.
+    pub fn root(
+        dir: &Path,
+        options: &IgnoreOptions,
+        warnings: &mut Vec<ConfigError>,
+    ) -> Arc<IgnoreStack> {
+        let mut files = Vec::new();
+        if options.global {
+            if let Some(global) = global_ignore_file() {
+                files.extend(IgnoreFile::read(&global, dir, warnings));
+            }
+        }
+
+        let global = Arc::new(IgnoreStack {
+            files,
+            skip_git_dir: options.vcs,
+            parent: None,
+        });
+        IgnoreStack::child(&global, dir, options, warnings)
+    }
+
+    /// The stack for `dir`, a folder inside the one `parent` was made for.
+    pub fn child(
+        parent: &Arc<IgnoreStack>,
+        dir: &Path,
+        options: &IgnoreOptions,
+        warnings: &mut Vec<ConfigError>,
+    ) -> Arc<IgnoreStack> {
+        // Highest precedence first.
+        let mut candidates = Vec::new();
+        if options.rgrep {
+            candidates.push(dir.join(RGREP_IGNORE_FILE));
+        }
+        if options.dot {
+            candidates.push(dir.join(".ignore"));
+        }
+        if options.vcs {
+            candidates.push(dir.join(".gitignore"));
+        }
+        if options.exclude {
+            candidates.push(dir.join(".git").join("info").join("exclude"));
+        }
+
+        let files: Vec<IgnoreFile> = candidates
+            .iter()
+            .filter_map(|file| IgnoreFile::read(file, dir, warnings))
+            .collect();
+
+        if files.is_empty() {
+            return Arc::clone(parent);
+        }
+        Arc::new(IgnoreStack {
+            files,
+            skip_git_dir: parent.skip_git_dir,
+            parent: Some(Arc::clone(parent)),
+        })
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.skip_git_dir && is_dir && path.file_name().is_some_and(|name| name == ".git") {
+            return true;
+        }
+
+        let mut stack = Some(self);
+        while let Some(current) = stack {
+            for file in &current.files {
+                if let Some(ignored) = file.matched(path, is_dir) {
+                    return ignored;
+                }
+            }
+            stack = current.parent.as_deref();
+        }
+
+        false
+    }
+}
+
+/// Git's global ignore file: `core.excludesFile` from `~/.gitconfig` if it's
+/// set, `$XDG_CONFIG_HOME/git/ignore` (or `~/.config/git/ignore`) otherwise.
+fn global_ignore_file() -> Option<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+
+    if let Some(home) = &home {
+        if let Ok(gitconfig) = fs::read_to_string(home.join(".gitconfig")) {
+            if let Some(path) = excludes_file(&gitconfig) {
+                return Some(match path.strip_prefix("~/") {
+                    Some(rest) => home.join(rest),
+                    None => PathBuf::from(path),
+                });
+            }
+        }
+    }
+
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(config_home) if !config_home.is_empty() => {
+            Some(PathBuf::from(config_home).join("git").join("ignore"))
+        }
+        _ => home.map(|home| home.join(".config").join("git").join("ignore")),
+    }
+}
+
+/// Looks for `excludesfile = ...` in the `[core]` section of a git config.
+fn excludes_file(gitconfig: &str) -> Option<&str> {
+    let mut in_core = false;
+    for line in gitconfig.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_core = line.eq_ignore_ascii_case("[core]");
+        } else if in_core {
+            if let Some((key, value)) = line.split_once('=') {
+                if key.trim().eq_ignore_ascii_case("excludesfile") {
+                    return Some(value.trim().trim_matches('"'));
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the rule parsed from `line` matches `path`.
+    fn matches(line: &str, path: &str, is_dir: bool) -> bool {
+        let rule = parse_rule(line, false).unwrap().unwrap();
+        rule.is_match(Path::new(path), is_dir)
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_no_rules() {
+        assert!(parse_rule("# comment", false).unwrap().is_none());
+        assert!(parse_rule("   ", false).unwrap().is_none());
+        assert!(parse_rule("!", false).unwrap().is_none());
+        assert!(matches("\\#notes", "#notes", false));
+        assert!(matches("\\!important", "!important", false));
+    }
+
+    #[test]
+    fn negation() {
+        assert!(parse_rule("!keep.log", false).unwrap().unwrap().negated);
+        assert!(!parse_rule("\\!keep.log", false).unwrap().unwrap().negated);
+    }
+
+    #[test]
+    fn a_slash_anchors_the_pattern() {
+        assert!(matches("build", "build", true));
+        assert!(matches("build", "src/build", true));
+        assert!(matches("/build", "build", true));
+        assert!(!matches("/build", "src/build", true));
+        assert!(matches("doc/*.txt", "doc/a.txt", false));
+        assert!(!matches("doc/*.txt", "src/doc/a.txt", false));
+        assert!(!matches("doc/*.txt", "doc/sub/a.txt", false));
+    }
+
+    #[test]
+    fn a_trailing_slash_only_matches_folders() {
+        assert!(matches("tmp/", "tmp", true));
+        assert!(!matches("tmp/", "tmp", false));
+        assert!(matches("tmp", "tmp", false));
+    }
+
+    #[test]
+    fn trailing_spaces_are_dropped_unless_escaped() {
+        assert!(matches("foo  ", "foo", false));
+        assert!(matches("foo\\ ", "foo ", false));
+        assert!(!matches("foo\\ ", "foo", false));
+    }
+
+    #[test]
+    fn double_star_matches_any_number_of_folders() {
+        assert!(matches("a/**/b", "a/b", false));
+        assert!(matches("a/**/b", "a/x/y/b", false));
+        assert!(matches("**/b", "x/b", false));
+        assert!(matches("a/**", "a/x/y", false));
+        assert!(!matches("a/*/b", "a/x/y/b", false));
+    }
+
+    #[test]
+    fn deeper_and_higher_precedence_files_win() {
+        let root = env::temp_dir().join(format!("rgrep-ignore-{}", std::process::id()));
+        let sub = root.join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(root.join(".gitignore"), "*.log\n").unwrap();
+        fs::write(sub.join(".gitignore"), "!keep.log\n").unwrap();
+        fs::write(sub.join(RGREP_IGNORE_FILE), "also.log\n").unwrap();
+        fs::write(sub.join(".ignore"), "!also.log\n").unwrap();
+
+        let options = IgnoreOptions {
+            global: false,
+            ..IgnoreOptions::all()
+        };
+        let mut warnings = Vec::new();
+        let stack = IgnoreStack::root(&root, &options, &mut warnings);
+        let sub_stack = IgnoreStack::child(&stack, &sub, &options, &mut warnings);
+        let ignored = [
+            stack.is_ignored(&root.join("a.log"), false),
+            sub_stack.is_ignored(&sub.join("b.log"), false),
+            sub_stack.is_ignored(&sub.join("keep.log"), false),
+            sub_stack.is_ignored(&sub.join("also.log"), false),
+            stack.is_ignored(&root.join(".git"), true),
+        ];
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(ignored, [true, true, false, true, true]);
+    }
+}
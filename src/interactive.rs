@@ -0,0 +1,191 @@
+//! `--interactive`: once the search is over, every match to replace is shown
+//! with the lines around it, and only the accepted ones are written. The
+//! questions go through `/dev/tty`, so it works even when stdout is piped.
+
+use crate::printer::display_path;
+use crate::replace::{self, FileEdits};
+use crate::searcher::Occurrence;
+use crate::{Config, ConfigError, SearchState};
+use colored::*;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write as _};
+
+/// Lines shown before and after the line of a match.
+const PROMPT_CONTEXT: usize = 2;
+
+enum Answer {
+    Yes,
+    No,
+    /// Accept this match and every other one left in the file.
+    AllInFile,
+    Quit,
+}
+
+pub struct Terminal {
+    input: BufReader<File>,
+    output: File,
+}
+
+impl Terminal {
+    pub fn open() -> Result<Terminal, ConfigError> {
+        let no_terminal = |err: std::io::Error| ConfigError::NoTerminal(err.to_string());
+        let tty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .map_err(no_terminal)?;
+        let output = tty.try_clone().map_err(no_terminal)?;
+
+        Ok(Terminal {
+            input: BufReader::new(tty),
+            output,
+        })
+    }
+
+    /// Shows the line of the match as it is and as it would be, with the
+    /// lines around it. With `-U` the "line" can be several ones.
+    fn show(&mut self, path: &str, lines: &[&str], occurrence: &Occurrence, config: &Config) {
+        let mut out = String::new();
+        let (number, _) = occurrence.line_range();
+        let _ = writeln!(out, "\n{}:{}", path.magenta(), number.to_string().green());
+
+        let line = &occurrence.line;
+        let first = occurrence.line_number;
+        let last = occurrence.last_line_number;
+        let context = |out: &mut String, line_number: usize| {
+            if let Some(line) = lines.get(line_number - 1) {
+                let _ = writeln!(out, "{}", format!("{:>6}   {}", line_number, line).dimmed());
+            }
+        };
+
+        for line_number in first.saturating_sub(PROMPT_CONTEXT).max(1)..first {
+            context(&mut out, line_number);
+        }
+
+        let old = (occurrence.start, occurrence.end);
+        write_marked(&mut out, first, "-".red(), line, old, |text| {
+            text.red().bold()
+        });
+        let (new, replaced) = replace::replace_line(line, [occurrence], config);
+        write_marked(&mut out, first, "+".green(), &new, replaced[0], |text| {
+            text.green().bold()
+        });
+
+        for line_number in last + 1..=last + PROMPT_CONTEXT {
+            context(&mut out, line_number);
+        }
+
+        let _ = self.output.write_all(out.as_bytes());
+    }
+
+    /// Asks until a valid answer is given. The end of the input counts as
+    /// quitting.
+    fn ask(&mut self) -> Answer {
+        let question = "Replace this match? [y]es, [n]o, [a]ll in this file, [q]uit: ";
+        loop {
+            let _ = write!(self.output, "{}", question.bold());
+            let _ = self.output.flush();
+
+            let mut answer = String::new();
+            match self.input.read_line(&mut answer) {
+                Ok(0) | Err(_) => return Answer::Quit,
+                Ok(_) => {}
+            }
+
+            match answer.trim().to_lowercase().as_str() {
+                "y" | "yes" => return Answer::Yes,
+                "n" | "no" => return Answer::No,
+                "a" | "all" => return Answer::AllInFile,
+                "q" | "quit" => return Answer::Quit,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Writes `text` one line at a time, numbered from `first`, with the part
+/// between `start` and `end` highlighted.
+fn write_marked(
+    out: &mut String,
+    first: usize,
+    sign: ColoredString,
+    text: &str,
+    (start, end): (usize, usize),
+    highlight: impl Fn(&str) -> ColoredString,
+) {
+    let mut from = 0;
+    for (line_number, line) in (first..).zip(text.strip_suffix('\n').unwrap_or(text).split('\n')) {
+        let to = from + line.len();
+        let (marked_start, marked_end) = (start.clamp(from, to), end.clamp(from, to));
+        let _ = writeln!(
+            out,
+            "{:>6} {} {}{}{}",
+            line_number,
+            sign,
+            &text[from..marked_start],
+            highlight(&text[marked_start..marked_end]),
+            &text[marked_end..to]
+        );
+        from = to + 1;
+    }
+}
+
+/// Asks about every match kept during the search, file by file, and writes
+/// each file with the matches accepted in it. After quitting, the matches
+/// already accepted are still written. Returns how many files were written.
+pub fn confirm_and_write(terminal: &mut Terminal, state: &SearchState, config: &Config) -> usize {
+    let mut written = 0;
+    for file in replace::take_files(state) {
+        let (accepted, quit) = match confirm_file(terminal, &file, config) {
+            Ok(confirmed) => confirmed,
+            Err(err) => {
+                state.warn(err);
+                continue;
+            }
+        };
+
+        let edits = replace::line_edits(&accepted, config);
+        if !edits.is_empty() {
+            match replace::write_file(&file.path, &edits, config.backup) {
+                Ok(()) => written += 1,
+                Err(err) => state.warn(err),
+            }
+        }
+
+        if quit {
+            break;
+        }
+    }
+    written
+}
+
+/// The matches of a file accepted, and whether the user quit.
+fn confirm_file<'a>(
+    terminal: &mut Terminal,
+    file: &'a FileEdits,
+    config: &Config,
+) -> Result<(Vec<&'a Occurrence>, bool), ConfigError> {
+    let content = fs::read_to_string(&file.path).map_err(|err| {
+        ConfigError::FileReadError(file.path.to_string_lossy().into_owned(), err.to_string())
+    })?;
+    let lines: Vec<&str> = content.lines().collect();
+    let path = display_path(&file.path, config);
+
+    let mut accepted = Vec::new();
+    let mut all_in_file = false;
+    for occurrence in &file.occurrences {
+        if !all_in_file {
+            terminal.show(&path, &lines, occurrence, config);
+            match terminal.ask() {
+                Answer::Yes => {}
+                Answer::No => continue,
+                Answer::AllInFile => all_in_file = true,
+                Answer::Quit => return Ok((accepted, true)),
+            }
+        }
+        accepted.push(occurrence);
+    }
+
+    Ok((accepted, false))
+}
@@ -0,0 +1,162 @@
+//! `--json` output. Every event is one JSON object on its own line, with a
+//! `type` field telling which event it is. The format is documented in the
+//! README and fields are only ever added to it, never renamed or removed.
+
+use crate::printer::{lines_in_order, PrintedLine};
+use crate::replace;
+use crate::searcher::{ContextLine, FileResult, Occurrence};
+use crate::{BinaryMode, Config, SearchState};
+use serde_json::{json, Value};
+use std::fmt::Write as _;
+use std::sync::atomic::Ordering;
+
+/// Writes the `begin`, `match`, `context` and `end` events of one file.
+pub fn write_file(out: &mut String, path: &str, result: &FileResult, config: &Config) {
+    write_event(out, json!({ "type": "begin", "path": path }));
+
+    let reported_only = result.binary && config.binary_mode == BinaryMode::Report;
+    let mut matched_lines = 0;
+
+    if !reported_only {
+        for printed_line in lines_in_order(result) {
+            match printed_line {
+                PrintedLine::Context(context_line) => {
+                    write_event(out, context_event(path, context_line));
+                }
+                // All the occurrences of a line go in a single event.
+                PrintedLine::Matched(same_line) => {
+                    matched_lines += 1;
+                    write_event(out, match_event(path, same_line, config));
+                }
+            }
+        }
+    }
+
+    write_event(
+        out,
+        json!({
+            "type": "end",
+            "path": path,
+            "binary": result.binary,
+            "stats": {
+                "matches": result.occurrences.len(),
+                "matched_lines": matched_lines,
+                "lines_searched": result.lines_searched,
+            },
+        }),
+    );
+}
+
+/// Writes the event of a file listed by `-l` or `-L`.
+pub fn write_file_listing(out: &mut String, path: &str, matched: bool) {
+    let event_type = if matched {
+        "file_with_matches"
+    } else {
+        "file_without_match"
+    };
+    write_event(out, json!({ "type": event_type, "path": path }));
+}
+
+/// Writes the `summary` event, printed once after the whole folder is searched.
+pub fn write_summary(out: &mut String, state: &SearchState) {
+    let warnings: Vec<String> = state
+        .warnings
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|warning| warning.to_string())
+        .collect();
+
+    write_event(
+        out,
+        json!({
+            "type": "summary",
+            "matched": state.at_least_once.load(Ordering::SeqCst),
+            "limit_reached": state.limit_reached(),
+            "stats": {
+                "files_searched": state.files_searched.load(Ordering::SeqCst),
+                "files_matched": state.files_matched.load(Ordering::SeqCst),
+                "matches": state.matches.load(Ordering::SeqCst),
+            },
+            "warnings": warnings,
+        }),
+    );
+}
+
+fn match_event(path: &str, occurrences: &[Occurrence], config: &Config) -> Value {
+    let first = &occurrences[0];
+    let line: &str = &first.line;
+
+    if config.invert {
+        return json!({
+            "type": "match",
+            "path": path,
+            "line_number": first.line_number,
+            "column": null,
+            "absolute_offset": first.line_offset,
+            "text": line,
+            "submatches": [],
+        });
+    }
+
+    let submatches: Vec<Value> = occurrences
+        .iter()
+        .map(|occurrence| {
+            let groups: Vec<Value> = occurrence
+                .groups
+                .iter()
+                .map(|group| {
+                    json!({
+                        "index": group.index,
+                        "name": group.name,
+                        "match": &line[group.start..group.end],
+                        "start": group.start,
+                        "end": group.end,
+                    })
+                })
+                .collect();
+
+            let (line_number, end_line_number) = occurrence.line_range();
+            json!({
+                "match": &line[occurrence.start..occurrence.end],
+                "pattern": &config.patterns[occurrence.pattern],
+                "line_number": line_number,
+                "end_line_number": end_line_number,
+                "start": occurrence.start,
+                "end": occurrence.end,
+                "column": occurrence.column(),
+                "absolute_offset": occurrence.absolute_offset(),
+                "groups": groups,
+            })
+        })
+        .collect();
+
+    let mut event = json!({
+        "type": "match",
+        "path": path,
+        "line_number": first.line_number,
+        "column": first.column(),
+        "absolute_offset": first.line_offset,
+        "text": line,
+        "submatches": submatches,
+    });
+    if config.replace.is_some() {
+        let (replaced, _) = replace::replace_line(line, occurrences, config);
+        event["replacement"] = Value::String(replaced);
+    }
+    event
+}
+
+fn context_event(path: &str, context_line: &ContextLine) -> Value {
+    json!({
+        "type": "context",
+        "path": path,
+        "line_number": context_line.line_number,
+        "absolute_offset": context_line.line_offset,
+        "text": context_line.line,
+    })
+}
+
+fn write_event(out: &mut String, event: Value) {
+    let _ = writeln!(out, "{}", event);
+}
@@ -1,12 +1,23 @@
 #![allow(non_snake_case)]
 
 use colored::*;
-use regex::Regex;
 use std::env;
-use std::fs;
 use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 use thiserror::Error;
 
+mod glob;
+mod ignore;
+mod interactive;
+mod json;
+mod matcher;
+mod printer;
+mod replace;
+mod searcher;
+mod types;
+mod walker;
 
 #[derive(Error, Debug)]
 pub enum ConfigError {
@@ -28,23 +39,274 @@ pub enum ConfigError {
     ReadDirError(String),
     #[error("Error processing directory entry: {0}")]
     EntryProcessingError(String),
-    #[error("Entry isn't a file")]
-    EntryNotFile,
-    #[error("There was an error reading a file {0}")]
-    FileReadError(String),
+    #[error("There was an error reading the file {0}: {1}")]
+    FileReadError(String, String),
+    #[error("The file {0} was skipped, it isn't valid UTF-8 (search it with --binary text)")]
+    SkippedNotUtf8(String),
+    #[error("The file {0} isn't valid UTF-8 from line {1} on, the rest of it was skipped (search it with --binary text)")]
+    NotUtf8FromLine(String, usize),
     #[error("Invalid Regex Format")]
     InvalidRegex,
+    #[error("The option {0} needs to be proceded by a value")]
+    NoValueForOption(String),
+    #[error("The argument of the option {0} needs to be a number")]
+    OptionNeedsNumber(String),
+    #[error("Invalid binary mode {0} (expected skip, report or text)")]
+    InvalidBinaryMode(String),
+    #[error("Invalid pattern in {0}: {1} ({2})")]
+    InvalidIgnorePattern(String, String, String),
+    #[error("Invalid glob {0} ({1})")]
+    InvalidGlob(String, String),
+    #[error("Unknown file type {0} (run with --type-list to see the available ones)")]
+    UnknownFileType(String),
+    #[error("Invalid file type definition {0} (expected name:glob)")]
+    InvalidTypeDefinition(String),
+    #[error("File type list display")]
+    TypeListRequested(Box<types::FileTypes>),
+    #[error("The argument of the option -j needs to be a number greater than 0, not {0}")]
+    InvalidThreadCount(String),
+    #[error("There was an error writing the file {0}: {1}")]
+    FileWriteError(String, String),
+    #[error("The option {0} can only be used along with {1}")]
+    OptionNeedsOption(String, String),
+    #[error("The options {0} and {1} can't be used together")]
+    IncompatibleOptions(String, String),
+    #[error("None of the patterns has a group {0}")]
+    UnknownCaptureGroup(String),
+    #[error("The option --interactive needs a terminal, /dev/tty can't be opened: {0}")]
+    NoTerminal(String),
+    #[error(
+        "The config file {0} can't give patterns with {1}, they only come from the command line"
+    )]
+    PatternInConfigFile(String, String),
+}
+
+/// What to do with a file whose first block looks binary.
+#[derive(Clone, Copy, PartialEq)]
+enum BinaryMode {
+    /// Don't search the file at all.
+    Skip,
+    /// Only say whether the file matches, never print its lines.
+    Report,
+    /// Search the file as text, replacing invalid UTF-8 with U+FFFD.
+    Text,
+}
+
+/// Set by `-l` and `-L`: only the paths of the files are printed.
+#[derive(Clone, Copy, PartialEq)]
+enum FileListing {
+    WithMatches,
+    WithoutMatch,
+}
+
+/// Set by `--column` and `--column-bytes`: how the column of an occurrence
+/// is counted.
+#[derive(Clone, Copy, PartialEq)]
+enum ColumnUnit {
+    Chars,
+    Bytes,
+}
+
+/// How the results are printed.
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    /// A colored header per file, then its matching lines.
+    Standard,
+    /// No headers, every line prefixed with its path, e.g. `path:line:text`.
+    Grep,
+    /// One JSON object per line, see the README for the events.
+    Json,
+    /// `path:line:column:text` once per match, without colors, for the
+    /// quickfix list of Vim and Emacs' compilation-mode.
+    Vimgrep,
 }
 
 struct Config {
-    search_string: String,
+    /// The patterns searched for, from `-e` and `-f`, or the first argument
+    /// if neither is given.
+    patterns: Vec<String>,
     max_lines: Option<usize>,
+    max_count: Option<usize>,
+    max_total: Option<usize>,
+    max_lines_per_file: Option<usize>,
+    max_files: Option<usize>,
     ignore_case: bool,
     only_count: bool,
-    regex: Option<Regex>,
+    /// Set by `-o`, and by `--capture` which only prints one group of the
+    /// match.
+    only_matching: bool,
+    capture: Option<matcher::Capture>,
+    use_regex: bool,
+    /// Set by `-U`: every file is searched as a whole, so that a match can
+    /// span several lines.
+    multiline: bool,
+    matcher: matcher::Matcher,
+    invert: bool,
+    list_files: Option<FileListing>,
+    null_separator: bool,
+    warnings_as_errors: bool,
+    quiet: bool,
+    binary_mode: BinaryMode,
+    mmap: bool,
+    threads: usize,
+    before_context: usize,
+    after_context: usize,
+    output: OutputFormat,
+    absolute_paths: bool,
+    column: Option<ColumnUnit>,
+    byte_offset: bool,
+    /// The template of `--replace`, with `$1` or `${name}` for the groups of
+    /// a regex.
+    replace: Option<String>,
+    diff: bool,
+    /// Set by `--write`, and by `--interactive` which only writes the
+    /// matches accepted.
+    write: bool,
+    interactive: bool,
+    backup: bool,
+    ignore: ignore::IgnoreOptions,
+    globs: glob::GlobFilter,
+    types: types::FileTypes,
     folder_name: String,
 }
 
+/// Everything the search workers share while walking the folder.
+struct SearchState {
+    max_lines: Option<AtomicUsize>,
+    matches_left: Option<AtomicUsize>,
+    files_left: Option<AtomicUsize>,
+    at_least_once: AtomicBool,
+    files_searched: AtomicUsize,
+    files_matched: AtomicUsize,
+    matches: AtomicUsize,
+    warnings: Mutex<Vec<ConfigError>>,
+    /// Edits waiting for `--write` until the search is over.
+    edits: Mutex<Vec<replace::FileEdits>>,
+}
+
+fn is_zero(value: &Option<AtomicUsize>) -> bool {
+    match value {
+        Some(value) => value.load(Ordering::SeqCst) == 0,
+        None => false,
+    }
+}
+
+impl SearchState {
+    fn new(config: &Config) -> SearchState {
+        SearchState {
+            max_lines: config.max_lines.map(AtomicUsize::new),
+            matches_left: config.max_total.map(AtomicUsize::new),
+            files_left: config.max_files.map(AtomicUsize::new),
+            at_least_once: AtomicBool::new(false),
+            files_searched: AtomicUsize::new(0),
+            files_matched: AtomicUsize::new(0),
+            matches: AtomicUsize::new(0),
+            warnings: Mutex::new(Vec::new()),
+            edits: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Takes one line out of the budget. Returns false if there was none left.
+    fn take_line(&self) -> bool {
+        match &self.max_lines {
+            Some(value) => value
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |value| {
+                    value.checked_sub(1)
+                })
+                .is_ok(),
+            None => true,
+        }
+    }
+
+    fn lines_left(&self) -> Option<usize> {
+        self.max_lines
+            .as_ref()
+            .map(|value| value.load(Ordering::SeqCst))
+    }
+
+    fn out_of_lines(&self) -> bool {
+        self.lines_left() == Some(0)
+    }
+
+    /// Takes up to `wanted` matches out of `--max-total`, returns how many
+    /// could be taken.
+    fn take_matches(&self, wanted: usize) -> usize {
+        match &self.matches_left {
+            Some(value) => match value.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |value| {
+                Some(value - value.min(wanted))
+            }) {
+                Ok(left) => left.min(wanted),
+                Err(_) => 0,
+            },
+            None => wanted,
+        }
+    }
+
+    fn out_of_matches(&self) -> bool {
+        is_zero(&self.matches_left)
+    }
+
+    /// Takes one file out of `--max-files`. Returns false if there was none left.
+    fn take_file(&self) -> bool {
+        match &self.files_left {
+            Some(value) => value
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |value| {
+                    value.checked_sub(1)
+                })
+                .is_ok(),
+            None => true,
+        }
+    }
+
+    fn out_of_files(&self) -> bool {
+        is_zero(&self.files_left)
+    }
+
+    /// True once one of the global limits is reached and nothing else
+    /// should be searched.
+    fn should_stop(&self) -> bool {
+        self.limit_reached().is_some()
+    }
+
+    /// Which of the global limits was reached, if any.
+    fn limit_reached(&self) -> Option<&'static str> {
+        if self.out_of_lines() {
+            Some("lines")
+        } else if self.out_of_matches() {
+            Some("matches")
+        } else if self.out_of_files() {
+            Some("files")
+        } else {
+            None
+        }
+    }
+
+    fn searched(&self) {
+        self.files_searched.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Records a file with `matches` occurrences in it.
+    fn found(&self, matches: usize) {
+        self.at_least_once.store(true, Ordering::SeqCst);
+        self.files_matched.fetch_add(1, Ordering::SeqCst);
+        self.matches.fetch_add(matches, Ordering::SeqCst);
+    }
+
+    fn warn(&self, warning: ConfigError) {
+        self.warnings.lock().unwrap().push(warning);
+    }
+
+    fn edit(&self, path: &std::path::Path, occurrences: Vec<searcher::Occurrence>) {
+        if !occurrences.is_empty() {
+            let path = path.to_path_buf();
+            self.edits
+                .lock()
+                .unwrap()
+                .push(replace::FileEdits { path, occurrences });
+        }
+    }
+}
+
 impl Config {
     fn new(args: &[String]) -> Result<Config, ConfigError> {
         if args.len() < 2 {
@@ -55,18 +317,65 @@ impl Config {
             return Err(ConfigError::HelpRequested);
         }
 
+        if args.iter().any(|arg| arg == "--type-list") {
+            let mut types = types::FileTypes::default();
+            for pair in args.windows(2) {
+                if pair[0] == "--type-add" {
+                    types.add(&pair[1])?;
+                }
+            }
+            return Err(ConfigError::TypeListRequested(Box::new(types)));
+        }
+
         if args.len() < 3 {
             return Err(ConfigError::NotEnoughArguments);
         }
 
-        let search_string = args[1].clone();
+        // With -e or -f the patterns come from the options, and the first
+        // argument is an option like any other.
+        let patterns_from_options = patterns_from_options(args);
+        let mut patterns = Vec::new();
+        if !patterns_from_options {
+            patterns.push(args[1].clone());
+        }
         let folder_name = args[args.len() - 1].clone();
         let mut max_lines = None;
-        let mut ignore_case = false;
+        let mut max_count = None;
+        let mut max_total = None;
+        let mut max_lines_per_file = None;
+        let mut max_files = None;
+        let mut case_mode = matcher::CaseMode::Sensitive;
         let mut only_count = false;
-        let mut regex = None;
+        let mut only_matching = false;
+        let mut capture = None;
+        let mut use_regex = false;
+        let mut multiline = false;
+        let mut invert = false;
+        let mut word = false;
+        let mut whole_line = false;
+        let mut list_files = None;
+        let mut null_separator = false;
+        let mut warnings_as_errors = false;
+        let mut quiet = false;
+        let mut binary_mode = BinaryMode::Skip;
+        let mut mmap = false;
+        let mut before_context = 0;
+        let mut after_context = 0;
+        let mut output = OutputFormat::Standard;
+        let mut absolute_paths = false;
+        let mut column = None;
+        let mut byte_offset = false;
+        let mut replace = None;
+        let mut diff = false;
+        let mut write = false;
+        let mut interactive = false;
+        let mut backup = false;
+        let mut ignore = ignore::IgnoreOptions::all();
+        let mut globs = glob::GlobFilter::default();
+        let mut types = types::FileTypes::default();
+        let mut threads = thread::available_parallelism().map_or(1, |n| n.get());
 
-        let mut i = 2;
+        let mut i = if patterns_from_options { 1 } else { 2 };
         while i < args.len() - 1 {
             match args[i].as_str() {
                 "-n" => {
@@ -86,7 +395,18 @@ impl Config {
                     }
                 }
                 "-i" => {
-                    ignore_case = true;
+                    case_mode = matcher::CaseMode::Insensitive;
+                    i += 1;
+                }
+                "-s" | "--case-sensitive" => {
+                    case_mode = matcher::CaseMode::Sensitive;
+                    i += 1;
+                }
+                "-S" | "--smart-case" => {
+                    case_mode = matcher::CaseMode::Smart;
+                    i += 1;
+                }
+                "--no-config" => {
                     i += 1;
                 }
                 "-c" => {
@@ -94,16 +414,247 @@ impl Config {
                     i += 1;
                 }
                 "-r" => {
-                    match Regex::new(&search_string) {
-                        Ok(r) => {
-                            regex = Some(r);
+                    use_regex = true;
+                    i += 1;
+                }
+                "-U" | "--multiline" => {
+                    multiline = true;
+                    i += 1;
+                }
+                "-v" | "--invert-match" => {
+                    invert = true;
+                    i += 1;
+                }
+                "-l" | "--files-with-matches" => {
+                    list_files = Some(FileListing::WithMatches);
+                    i += 1;
+                }
+                "-L" | "--files-without-match" => {
+                    list_files = Some(FileListing::WithoutMatch);
+                    i += 1;
+                }
+                "-0" | "--null" => {
+                    null_separator = true;
+                    i += 1;
+                }
+                "--warnings-as-errors" => {
+                    warnings_as_errors = true;
+                    i += 1;
+                }
+                "-w" | "--word-regexp" => {
+                    word = true;
+                    i += 1;
+                }
+                "-x" | "--line-regexp" => {
+                    whole_line = true;
+                    i += 1;
+                }
+                "-o" | "--only-matching" => {
+                    only_matching = true;
+                    i += 1;
+                }
+                "--capture" => {
+                    if i + 2 < args.len() {
+                        capture = Some(args[i + 1].clone());
+                        i += 2;
+                    } else {
+                        return Err(ConfigError::NoValueForOption(args[i].clone()));
+                    }
+                }
+                "-e" | "--regexp" => {
+                    if i + 2 < args.len() {
+                        patterns.push(args[i + 1].clone());
+                        i += 2;
+                    } else {
+                        return Err(ConfigError::NoValueForOption(args[i].clone()));
+                    }
+                }
+                "-f" | "--file" => {
+                    if i + 2 < args.len() {
+                        patterns.extend(read_pattern_file(&args[i + 1])?);
+                        i += 2;
+                    } else {
+                        return Err(ConfigError::NoValueForOption(args[i].clone()));
+                    }
+                }
+                "-q" | "--quiet" => {
+                    quiet = true;
+                    i += 1;
+                }
+                "-m" | "--max-count" | "--max-total" | "--max-lines-per-file" | "--max-files" => {
+                    if i + 2 < args.len() {
+                        let limit = match args[i + 1].parse::<usize>() {
+                            Ok(result) => Some(result),
+                            Err(_) => {
+                                return Err(ConfigError::OptionNeedsNumber(args[i].clone()));
+                            }
+                        };
+
+                        match args[i].as_str() {
+                            "-m" | "--max-count" => max_count = limit,
+                            "--max-total" => max_total = limit,
+                            "--max-lines-per-file" => max_lines_per_file = limit,
+                            _ => max_files = limit,
                         }
-                        Err(_) => {
-                            return Err(ConfigError::InvalidRegex);
+                        i += 2;
+                    } else {
+                        return Err(ConfigError::NoValueForOption(args[i].clone()));
+                    }
+                }
+                "-A" | "-B" | "-C" => {
+                    if i + 2 < args.len() {
+                        let lines = match args[i + 1].parse::<usize>() {
+                            Ok(result) => result,
+                            Err(_) => {
+                                return Err(ConfigError::OptionNeedsNumber(args[i].clone()));
+                            }
+                        };
+
+                        match args[i].as_str() {
+                            "-A" => after_context = lines,
+                            "-B" => before_context = lines,
+                            _ => {
+                                before_context = lines;
+                                after_context = lines;
+                            }
+                        }
+                        i += 2;
+                    } else {
+                        return Err(ConfigError::NoValueForOption(args[i].clone()));
+                    }
+                }
+                "-j" => {
+                    if i + 2 < args.len() {
+                        match args[i + 1].parse::<usize>() {
+                            Ok(result) if result > 0 => {
+                                threads = result;
+                            }
+                            _ => {
+                                return Err(ConfigError::InvalidThreadCount(args[i + 1].clone()));
+                            }
+                        }
+
+                        i += 2;
+                    } else {
+                        return Err(ConfigError::NoValueForOption(args[i].clone()));
+                    }
+                }
+                "-g" | "--glob" | "--iglob" => {
+                    if i + 2 < args.len() {
+                        globs.add(&args[i + 1], args[i] == "--iglob")?;
+                        i += 2;
+                    } else {
+                        return Err(ConfigError::NoValueForOption(args[i].clone()));
+                    }
+                }
+                "-t" | "--type" | "-T" | "--type-not" | "--type-add" => {
+                    if i + 2 < args.len() {
+                        match args[i].as_str() {
+                            "-t" | "--type" => types.select(&args[i + 1]),
+                            "-T" | "--type-not" => types.negate(&args[i + 1]),
+                            _ => types.add(&args[i + 1])?,
                         }
+                        i += 2;
+                    } else {
+                        return Err(ConfigError::NoValueForOption(args[i].clone()));
+                    }
+                }
+                "--absolute" => {
+                    absolute_paths = true;
+                    i += 1;
+                }
+                "--no-heading" => {
+                    output = OutputFormat::Grep;
+                    i += 1;
+                }
+                "--replace" => {
+                    if i + 2 < args.len() {
+                        replace = Some(args[i + 1].clone());
+                        i += 2;
+                    } else {
+                        return Err(ConfigError::NoValueForOption(args[i].clone()));
                     }
+                }
+                "--diff" => {
+                    diff = true;
+                    i += 1;
+                }
+                "--write" => {
+                    write = true;
+                    i += 1;
+                }
+                "--interactive" => {
+                    interactive = true;
+                    i += 1;
+                }
+                "--backup" => {
+                    backup = true;
+                    i += 1;
+                }
+                "--column" => {
+                    column = Some(ColumnUnit::Chars);
+                    i += 1;
+                }
+                "--column-bytes" => {
+                    column = Some(ColumnUnit::Bytes);
+                    i += 1;
+                }
+                "-b" | "--byte-offset" => {
+                    byte_offset = true;
+                    i += 1;
+                }
+                "--json" => {
+                    output = OutputFormat::Json;
+                    i += 1;
+                }
+                "--vimgrep" => {
+                    output = OutputFormat::Vimgrep;
+                    i += 1;
+                }
+                "--no-ignore" => {
+                    ignore = ignore::IgnoreOptions::none();
+                    i += 1;
+                }
+                "--no-ignore-vcs" => {
+                    ignore.vcs = false;
                     i += 1;
                 }
+                "--no-ignore-exclude" => {
+                    ignore.exclude = false;
+                    i += 1;
+                }
+                "--no-ignore-global" => {
+                    ignore.global = false;
+                    i += 1;
+                }
+                "--no-ignore-dot" => {
+                    ignore.dot = false;
+                    i += 1;
+                }
+                "--no-ignore-rgrep" => {
+                    ignore.rgrep = false;
+                    i += 1;
+                }
+                "--mmap" => {
+                    mmap = true;
+                    i += 1;
+                }
+                "--binary" => {
+                    if i + 2 < args.len() {
+                        binary_mode = match args[i + 1].as_str() {
+                            "skip" => BinaryMode::Skip,
+                            "report" => BinaryMode::Report,
+                            "text" => BinaryMode::Text,
+                            other => {
+                                return Err(ConfigError::InvalidBinaryMode(other.to_string()));
+                            }
+                        };
+
+                        i += 2;
+                    } else {
+                        return Err(ConfigError::NoValueForOption(args[i].clone()));
+                    }
+                }
                 _ => {
                     let invalid_option = args[i].clone();
                     return Err(ConfigError::InvalidOption(invalid_option));
@@ -111,33 +662,271 @@ impl Config {
             }
         }
 
+        types.build()?;
+        let ignore_case = case_mode.ignore_case(&patterns, use_regex);
+        let boundary = if whole_line {
+            matcher::Boundary::Line
+        } else if word {
+            matcher::Boundary::Word
+        } else {
+            matcher::Boundary::None
+        };
+        let matcher =
+            matcher::Matcher::new(&patterns, use_regex, ignore_case, boundary, multiline)?;
+
+        let capture = match capture {
+            Some(group) if !use_regex => {
+                return Err(ConfigError::OptionNeedsOption(
+                    format!("--capture {}", group),
+                    "-r".to_string(),
+                ));
+            }
+            Some(group) => {
+                let capture = matcher::Capture::parse(&group);
+                if !matcher.has_group(&capture) {
+                    return Err(ConfigError::UnknownCaptureGroup(group));
+                }
+                only_matching = true;
+                Some(capture)
+            }
+            None => None,
+        };
+        if only_matching {
+            for (set, option) in [(invert, "-v"), (replace.is_some(), "--replace")] {
+                if set {
+                    return Err(ConfigError::IncompatibleOptions(
+                        "-o".to_string(),
+                        option.to_string(),
+                    ));
+                }
+            }
+        }
+
+        if multiline && invert {
+            return Err(ConfigError::IncompatibleOptions(
+                "-U".to_string(),
+                "-v".to_string(),
+            ));
+        }
+
+        if replace.is_none() {
+            for (set, option) in [
+                (diff, "--diff"),
+                (write, "--write"),
+                (interactive, "--interactive"),
+            ] {
+                if set {
+                    return Err(ConfigError::OptionNeedsOption(
+                        option.to_string(),
+                        "--replace".to_string(),
+                    ));
+                }
+            }
+        } else if invert {
+            return Err(ConfigError::IncompatibleOptions(
+                "--replace".to_string(),
+                "-v".to_string(),
+            ));
+        }
+        // `-l`, `-L` and `-q` are done with a file before it could be
+        // edited, and a diff is neither JSON nor the lines Vim expects.
+        let listing = list_files.map(|listing| match listing {
+            FileListing::WithMatches => "-l",
+            FileListing::WithoutMatch => "-L",
+        });
+        let diff_output = match output {
+            OutputFormat::Json => Some("--json"),
+            OutputFormat::Vimgrep => Some("--vimgrep"),
+            _ => None,
+        };
+        for (set, option, output) in [
+            (diff, "--diff", diff_output),
+            (write, "--write", None),
+            (interactive, "--interactive", None),
+        ] {
+            let other = [listing, quiet.then_some("-q"), output]
+                .into_iter()
+                .flatten()
+                .next();
+            if let (true, Some(other)) = (set, other) {
+                return Err(ConfigError::IncompatibleOptions(
+                    option.to_string(),
+                    other.to_string(),
+                ));
+            }
+        }
+        write |= interactive;
+        if backup && !write {
+            return Err(ConfigError::OptionNeedsOption(
+                "--backup".to_string(),
+                "--write".to_string(),
+            ));
+        }
+
+        if quiet {
+            // The first file found is enough to know the exit status.
+            max_files = Some(max_files.map_or(1, |max: usize| max.min(1)));
+        }
+
         Ok(Config {
-            search_string,
+            patterns,
             max_lines,
+            max_count,
+            max_total,
+            max_lines_per_file,
+            max_files,
             ignore_case,
             only_count,
-            regex,
+            only_matching,
+            capture,
+            use_regex,
+            multiline,
+            matcher,
+            invert,
+            list_files,
+            null_separator,
+            warnings_as_errors,
+            quiet,
+            binary_mode,
+            mmap,
+            threads,
+            before_context,
+            after_context,
+            output,
+            absolute_paths,
+            column,
+            byte_offset,
+            replace,
+            diff,
+            write,
+            interactive,
+            backup,
+            ignore,
+            globs,
+            types,
             folder_name,
         })
     }
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+/// Whether the patterns are given with `-e` or `-f` instead of as the first
+/// argument. The folder, always last, is never looked at.
+fn patterns_from_options(args: &[String]) -> bool {
+    args[1..args.len() - 1]
+        .iter()
+        .any(|arg| matches!(arg.as_str(), "-e" | "--regexp" | "-f" | "--file"))
+}
+
+/// Reads the patterns of `-f`, one per line. An empty line is a pattern
+/// too, matching every line, like in grep.
+fn read_pattern_file(path: &str) -> Result<Vec<String>, ConfigError> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| ConfigError::FileReadError(path.to_string(), err.to_string()))?;
+
+    Ok(content
+        .lines()
+        .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+        .collect())
+}
+
+/// Exit statuses, the same as grep's.
+const EXIT_MATCH: i32 = 0;
+const EXIT_NO_MATCH: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
+/// Name of the config file looked for in the home folder, unless
+/// `RGREP_CONFIG_PATH` points somewhere else.
+const CONFIG_FILE: &str = ".rgreprc";
+
+/// Adds the options from the config file right after the searched string (or
+/// the program name if the patterns are given with `-e` or `-f`), so that
+/// the ones given on the command line come later and win. The file has
+/// one option (or option value) per line, lines starting with `#` are
+/// comments. Nothing is added if the file doesn't exist or `--no-config` is
+/// given. `-e` and `-f` aren't allowed in the file, where the patterns come
+/// from is only up to the command line.
+fn with_config_file_args(mut args: Vec<String>) -> Result<Vec<String>, ConfigError> {
+    if args.len() < 3 || args.iter().any(|arg| arg == "--no-config") {
+        return Ok(args);
+    }
+
+    let config_path = match env::var_os("RGREP_CONFIG_PATH") {
+        Some(path) if !path.is_empty() => std::path::PathBuf::from(path),
+        _ => match env::var_os("HOME") {
+            Some(home) => std::path::Path::new(&home).join(CONFIG_FILE),
+            None => return Ok(args),
+        },
+    };
+
+    let content = match std::fs::read_to_string(&config_path) {
+        Ok(content) => content,
+        Err(err) => {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                let warning = ConfigError::FileReadError(
+                    config_path.to_string_lossy().into_owned(),
+                    err.to_string(),
+                );
+                eprintln!("{}", warning.to_string().yellow());
+            }
+            return Ok(args);
+        }
+    };
+
+    let config_args: Vec<String> = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+    if let Some(option) = config_args
+        .iter()
+        .find(|arg| matches!(arg.as_str(), "-e" | "--regexp" | "-f" | "--file"))
+    {
+        return Err(ConfigError::PatternInConfigFile(
+            config_path.to_string_lossy().into_owned(),
+            option.clone(),
+        ));
+    }
+    let at = if patterns_from_options(&args) { 1 } else { 2 };
+    args.splice(at..at, config_args);
+    Ok(args)
+}
 
-    let decoy = Config::new(&args);
+fn main() {
+    let decoy = with_config_file_args(env::args().collect()).and_then(|args| Config::new(&args));
 
-    match decoy {
+    let exit_code = match decoy {
         Ok(result) => {
             let config = result;
 
-            let folder_path = config.folder_name;
-            let folder_path = std::path::Path::new(&folder_path);
-            let mut max_number_of_lines = config.max_lines;
-            let mut at_least_once = false;
+            let folder_path = std::path::Path::new(&config.folder_name);
+            let state = SearchState::new(&config);
+
+            // Opened before the search, so that it doesn't run for nothing.
+            let mut terminal = None;
+            if config.interactive {
+                match interactive::Terminal::open() {
+                    Ok(opened) => terminal = Some(opened),
+                    Err(err) => {
+                        eprintln!("{}", err);
+                        process::exit(EXIT_ERROR);
+                    }
+                }
+            }
+
+            if config.output == OutputFormat::Vimgrep {
+                // Editors read the output as is, escape codes included.
+                colored::control::set_override(false);
+            }
 
-            println!();
-            if config.regex.is_some() {
+            let standard = config.output == OutputFormat::Standard
+                && config.list_files.is_none()
+                && !config.quiet
+                && !config.diff;
+            if standard {
+                println!();
+            }
+            if standard && config.use_regex {
                 let printed_text = "The search will be done using Regex\n"
                     .purple()
                     .bold()
@@ -145,25 +934,67 @@ fn main() {
                 println!("{}", printed_text);
             }
 
-            match folder_iterator(
-                folder_path,
-                &config.search_string,
-                &mut max_number_of_lines,
-                config.ignore_case,
-                config.only_count,
-                &mut at_least_once,
-                &config.regex,
-            ) {
+            match walker::folder_iterator(folder_path, &config, &state) {
                 Ok(()) => {
-                    if !at_least_once {
-                        let attention = "The word ".blue().bold();
-                        let attention1 = " hasn't been found in any of the files.".blue().bold();
-                        let search_string = config.search_string.red().bold();
-                        println!("{}\"{}\"{}", attention, search_string, attention1);
+                    if standard && !state.at_least_once.load(Ordering::SeqCst) {
+                        let (attention, attention1) = if config.patterns.len() == 1 {
+                            ("The word ", " hasn't been found in any of the files.")
+                        } else {
+                            (
+                                "None of the words ",
+                                " have been found in any of the files.",
+                            )
+                        };
+                        println!(
+                            "{}{}{}",
+                            attention.blue().bold(),
+                            printer::quoted_patterns(&config.patterns).red().bold(),
+                            attention1.blue().bold()
+                        );
+                    }
+                    if standard {
+                        if let Some(limit) = state.limit_reached() {
+                            let stopped = format!(
+                                "The search stopped early, the maximum number of {} was reached.",
+                                limit
+                            );
+                            println!("{}", stopped.yellow().bold());
+                        }
+                    }
+
+                    if config.write {
+                        let written = match &mut terminal {
+                            Some(terminal) => {
+                                interactive::confirm_and_write(terminal, &state, &config)
+                            }
+                            None => replace::write_files(&state, &config),
+                        };
+                        if standard {
+                            let written =
+                                format!("The replacements were written to {} file(s).", written);
+                            println!("{}", written.green().bold());
+                        }
+                    }
+
+                    if config.output == OutputFormat::Json && !config.quiet {
+                        printer::print_json_summary(&state);
+                    }
+
+                    let at_least_once = state.at_least_once.load(Ordering::SeqCst);
+                    let warnings = state.warnings.into_inner().unwrap();
+                    print_warnings(&warnings);
+
+                    if config.warnings_as_errors && !warnings.is_empty() {
+                        EXIT_ERROR
+                    } else if at_least_once {
+                        EXIT_MATCH
+                    } else {
+                        EXIT_NO_MATCH
                     }
                 }
                 Err(err) => {
                     eprintln!("{}", err);
+                    EXIT_ERROR
                 }
             }
         }
@@ -174,290 +1005,105 @@ fn main() {
             println!("<>(Mandatory) the rest are optional\n");
             println!("Command line arguments:");
             println!("<test_string>: The string that's supposed to be searched for");
+            println!("-e, --regexp <pattern>: Search for this pattern, instead of the first argument (repeatable)");
+            println!(
+                "-f, --file <file>: Search for the patterns in the file, one per line (repeatable)"
+            );
             println!("-n <number>: Max number of lines (default: infinite)");
+            println!(
+                "-m, --max-count <number>: Max number of matches per file (default: infinite)"
+            );
+            println!(
+                "--max-total <number>: Max number of matches in all the files (default: infinite)"
+            );
+            println!("--max-lines-per-file <number>: Max number of lines searched in each file (default: infinite)");
+            println!("--max-files <number>: Max number of files with matches printed (default: infinite)");
             println!("-i: Ignore case (default: off)");
+            println!(
+                "-s, --case-sensitive: Search case sensitively, overriding -i and -S (default: on)"
+            );
+            println!("-S, --smart-case: Ignore case only if the string has no uppercase letters (default: off)");
+            println!("-w, --word-regexp: Only match whole words (default: off)");
+            println!("-x, --line-regexp: Only match whole lines, wins over -w (default: off)");
+            println!("-o, --only-matching: Only print the matched part of the lines, one match per line (default: off)");
+            println!("--capture <number|name>: With -r, only print what the group matched, one match per line (default: off)");
             println!("-c: Only count (prints only the number of matches in a file) (default: off)");
             println!("-r: Option to enable regex searching (default: off)");
+            println!("-U, --multiline: Search every file as a whole, so that a match can span several lines, . matches a newline only after (?s) (default: off)");
+            println!(
+                "-j <number>: Number of threads searching in parallel (default: number of CPUs)"
+            );
+            println!("--absolute: Print absolute paths instead of paths relative to the folder (default: off)");
+            println!("--no-heading: Print path:line:text for every match instead of a header per file (default: off)");
+            println!("--replace <template>: Print the matching lines with the matches replaced, $1 or ${{name}} stand for the groups of a regex (default: off)");
+            println!(
+                "--diff: With --replace, print a unified diff of every file instead (default: off)"
+            );
+            println!("--write: With --replace, write the changes to the files once the search is over (default: off)");
+            println!("--interactive: With --replace, ask about every match once the search is over and write the accepted ones (default: off)");
+            println!("--backup: With --write, keep the original of every file written as <file>.bak (default: off)");
+            println!("--column: Print the column (in characters, starting at 1) of every match (default: off)");
+            println!("--column-bytes: Same as --column, but the column is counted in bytes (default: off)");
+            println!("-b, --byte-offset: Print the byte offset in the file of every match (default: off)");
+            println!("--vimgrep: Print path:line:column:text once per match, without colors, for Vim's and Emacs' quickfix (default: off)");
+            println!("--json: Print the results as JSON Lines, one event per line (default: off)");
+            println!("-g, --glob <glob>: Only search files matching the glob, or skip them if it starts with ! (repeatable)");
+            println!("--iglob <glob>: Same as --glob, but case insensitive (repeatable)");
+            println!("-t, --type <type>: Only search files of the given type (repeatable)");
+            println!("-T, --type-not <type>: Don't search files of the given type (repeatable)");
+            println!("--type-add <name:glob>: Add a glob to a file type, creating it if needed (repeatable)");
+            println!("--type-list: Print the known file types and their globs");
+            println!("--no-ignore: Don't skip anything listed in ignore files (default: off)");
+            println!(
+                "--no-ignore-vcs: Don't honor .gitignore files or skip .git folders (default: off)"
+            );
+            println!("--no-ignore-exclude: Don't honor .git/info/exclude (default: off)");
+            println!("--no-ignore-global: Don't honor the global git ignore file (default: off)");
+            println!("--no-ignore-dot: Don't honor .ignore files (default: off)");
+            println!("--no-ignore-rgrep: Don't honor .rgrepignore files (default: off)");
+            println!(
+                "-v, --invert-match: Print the lines that don't contain the string (default: off)"
+            );
+            println!("-l, --files-with-matches: Only print the paths of the files that contain the string (default: off)");
+            println!("-L, --files-without-match: Only print the paths of the files that don't contain the string (default: off)");
+            println!("-0, --null: End the paths printed by -l and -L with a NUL byte instead of a newline (default: off)");
+            println!("--binary <skip|report|text>: What to do with binary files (default: skip)");
+            println!(
+                "--mmap: Memory-map large files instead of reading them in chunks (default: off)"
+            );
+            println!("--no-config: Don't read the options in ~/.rgreprc (or $RGREP_CONFIG_PATH)");
+            println!("-q, --quiet: Print nothing and stop at the first match, only the exit status tells if something was found (default: off)");
+            println!("--warnings-as-errors: Exit with status 2 if any file had to be skipped (default: off)");
             println!("<test_folder>: The name of folder that's gonna be searched\n");
+            println!("Exit status: 0 if something was found, 1 if nothing was, 2 on error");
+            EXIT_MATCH
+        }
+        Err(ConfigError::TypeListRequested(types)) => {
+            types.print_list();
+            EXIT_MATCH
         }
         Err(err) => {
             eprintln!("{}", err);
+            EXIT_ERROR
         }
     };
-}
-
-fn folder_iterator(
-    dir: &std::path::Path,
-    word_to_search: &String,
-    max_lines: &mut Option<usize>,
-    ignore_case: bool,
-    only_count: bool,
-    at_least_once: &mut bool,
-    regex: &Option<Regex>,
-) -> Result<(), ConfigError> {
-    if dir.is_dir() {
-        let entries;
-
-        match fs::read_dir(dir) {
-            Ok(result) => {
-                entries = result;
-
-                for entry in entries {
-                    match entry {
-                        Ok(result) => {
-                            let entry = result;
-
-                            match entry.file_type() {
-                                Ok(result) => {
-                                    if result.is_dir() {
-                                        let folder_path = entry.path();
-
-                                        match folder_iterator(
-                                            &folder_path,
-                                            word_to_search,
-                                            max_lines,
-                                            ignore_case,
-                                            only_count,
-                                            at_least_once,
-                                            regex,
-                                        ) {
-                                            Ok(()) => {}
-                                            Err(err) => {
-                                                return Err(err);
-                                            }
-                                        }
-                                    } else if result.is_file() {
-                                        let file_name = entry.file_name();
-
-                                        let file_name_str = file_name.to_string_lossy();
-
-                                        let file_path = entry.path();
-
-                                        match fs::read_to_string(&file_path) {
-                                            Ok(mut content) => {
-                                                if let Some(value) = max_lines {
-                                                    if *value > 0 {
-                                                        let word;
-
-                                                        if ignore_case {
-                                                            content = content.to_lowercase();
-                                                            word = word_to_search
-                                                                .clone()
-                                                                .to_lowercase();
-                                                        } else {
-                                                            word = word_to_search.clone();
-                                                        }
-
-                                                        if content.contains(&word) {
-                                                            *at_least_once = true;
-                                                            let first = "The file ".bold().green();
-                                                            let second = " contains the string "
-                                                                .bold()
-                                                                .green();
-                                                            let third: ColoredString =
-                                                                if ignore_case {
-                                                                    " (ignore case is on)".green()
-                                                                } else {
-                                                                    "".green()
-                                                                };
-
-                                                            println!(
-                                                                "{}\"{}\"{}\"{}\"{}\n",
-                                                                first,
-                                                                file_name_str,
-                                                                second,
-                                                                word_to_search,
-                                                                third
-                                                            );
-                                                        }
-                                                        if let Some(regex) = regex {
-                                                            if regex.is_match(&content) {
-                                                                *at_least_once = true;
-                                                                let first =
-                                                                    "The file ".bold().green();
-                                                                let second = " contains a substring that the Regex ".bold().green();
-                                                                let third = if ignore_case {
-                                                                    " has found (ignore case is on)"
-                                                                        .green()
-                                                                } else {
-                                                                    " has found".green()
-                                                                };
-
-                                                                println!(
-                                                                    "{}\"{}\"{}\"{}\"{}\n",
-                                                                    first,
-                                                                    file_name_str,
-                                                                    second,
-                                                                    word_to_search,
-                                                                    third
-                                                                );
-                                                            }
-                                                        }
-
-                                                        finds(
-                                                            &word, content, max_lines, only_count,
-                                                            regex,
-                                                        );
-                                                    } else {
-                                                        process::exit(0);
-                                                    }
-                                                } else {
-                                                    let word;
-
-                                                    if ignore_case {
-                                                        content = content.to_lowercase();
-                                                        word =
-                                                            word_to_search.clone().to_lowercase();
-                                                    } else {
-                                                        word = word_to_search.clone();
-                                                    }
-
-                                                    if content.contains(&word) {
-                                                        *at_least_once = true;
-                                                        let first = "The file ".bold().green();
-                                                        let second =
-                                                            " contains a substring that the Regex "
-                                                                .bold()
-                                                                .green();
-
-                                                        let third = if ignore_case {
-                                                            " has found (ignore case is on)".green()
-                                                        } else {
-                                                            " has found".green()
-                                                        };
-
-                                                        println!(
-                                                            "{}\"{}\"{}\"{}\"{}\n",
-                                                            first,
-                                                            file_name_str,
-                                                            second,
-                                                            word_to_search,
-                                                            third
-                                                        );
-                                                    }
-                                                    if let Some(regex) = regex {
-                                                        if regex.is_match(&content) {
-                                                            *at_least_once = true;
-                                                            let first = "The file ".bold().green();
-                                                            let second = " contains the string "
-                                                                .bold()
-                                                                .green();
-                                                            let third = if ignore_case {
-                                                                " (ignore case is on)".green()
-                                                            } else {
-                                                                "".green()
-                                                            };
-
-                                                            println!(
-                                                                "{}\"{}\"{}\"{}\"{}\n",
-                                                                first,
-                                                                file_name_str,
-                                                                second,
-                                                                word_to_search,
-                                                                third
-                                                            );
-                                                        }
-                                                    }
-
-                                                    finds(
-                                                        &word, content, max_lines, only_count,
-                                                        regex,
-                                                    );
-                                                }
-                                            }
-                                            Err(err) => {
-                                                return Err(ConfigError::FileReadError(
-                                                    err.to_string(),
-                                                ));
-                                            }
-                                        }
-                                    }
-                                }
-                                Err(_) => {
-                                    return Err(ConfigError::EntryNotFile);
-                                }
-                            }
-                        }
-                        Err(err) => {
-                            return Err(ConfigError::EntryProcessingError(err.to_string()));
-                        }
-                    }
-                }
-            }
-            Err(_) => {
-                let error_dir = dir.to_string_lossy().into_owned();
-                return Err(ConfigError::ReadDirError(error_dir));
-            }
-        }
-    } else {
-        let invalid_path = dir.to_string_lossy().into_owned();
-        return Err(ConfigError::IsNotAFolder(invalid_path));
-    }
 
-    Ok(())
+    process::exit(exit_code);
 }
 
-fn finds(
-    word_to_search: &String,
-    file: String,
-    max_lines: &mut Option<usize>,
-    only_count: bool,
-    regex: &Option<Regex>,
-) {
-    let mut occurrences = Vec::new();
-    for (line_number, line) in file.lines().enumerate() {
-        if let Some(regex) = regex {
-            for cap in regex.captures_iter(line) {
-                for (_, m) in cap.iter().enumerate() {
-                    if let Some(match_str) = m {
-                        let start = match_str.start();
-                        let end = match_str.end();
-
-                        let first_third: &str = &line[..start];
-                        let second_third: String = line[start..end].blue().to_string();
-                        let third_third: &str = &line[end..];
-
-                        occurrences.push((line_number + 1, first_third, second_third, third_third));
-                    }
-                }
-            }
-        } else {
-            for (s, _) in line.match_indices(word_to_search) {
-                let first_third: &str = &line[..s];
-                let second_third: String = line[s..s + word_to_search.len()].blue().to_string();
-                let third_third: &str = &line[s + word_to_search.len()..];
-
-                occurrences.push((line_number + 1, first_third, second_third, third_third));
-            }
-        }
-
-        if let Some(value) = max_lines {
-            *value -= 1;
-
-            if *value == 0 {
-                break;
-            }
-        }
+fn print_warnings(warnings: &[ConfigError]) {
+    if warnings.is_empty() {
+        return;
     }
 
-    let num_occurrences = occurrences.len();
-    if num_occurrences != 0 {
-        let number_of_occurrences = "Number of occurrences: ".red();
-
-        if !only_count {
-            println!("{}{}\n", number_of_occurrences, num_occurrences);
-            for (line_num, f, s, t) in occurrences {
-                println!("({}) {}{}{}", line_num, f, s, t);
-            }
-        } else {
-            println!("{}{}", number_of_occurrences, num_occurrences);
-        }
-        if let Some(value) = max_lines {
-            let line_left = "\nLines left to search: ".red();
-            println!("{}{}\n", line_left, value);
-        } else {
-            let line_left = "\nLines left to search: ".red();
-            println!("{}Inf\n", line_left,);
-        }
+    let title = format!(
+        "\n{} file(s) or folder(s) had to be skipped:",
+        warnings.len()
+    )
+    .yellow()
+    .bold();
+    eprintln!("{}", title);
+    for warning in warnings {
+        eprintln!("{}", warning.to_string().yellow());
     }
-}
\ No newline at end of file
+}
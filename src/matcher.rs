@@ -0,0 +1,433 @@
+use crate::ConfigError;
+use aho_corasick::{AhoCorasick, MatchKind};
+use regex::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
+use regex_syntax::ast::{self, Ast, ClassSetItem, LiteralKind, Visitor};
+
+/// Set by `-s`, `-i` and `-S`, the last one given wins.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CaseMode {
+    Sensitive,
+    Insensitive,
+    /// Insensitive unless the pattern has an uppercase letter in it.
+    Smart,
+}
+
+impl CaseMode {
+    /// With several patterns, smart case is sensitive as soon as one of them
+    /// has an uppercase letter.
+    pub fn ignore_case(self, patterns: &[String], use_regex: bool) -> bool {
+        match self {
+            CaseMode::Sensitive => false,
+            CaseMode::Insensitive => true,
+            CaseMode::Smart => !patterns
+                .iter()
+                .any(|pattern| has_uppercase_literal(pattern, use_regex)),
+        }
+    }
+}
+
+/// For a regex only the characters written as they are count, so `\W` or
+/// `\p{Lu}` don't make the search case sensitive.
+fn has_uppercase_literal(pattern: &str, use_regex: bool) -> bool {
+    if !use_regex {
+        return pattern.chars().any(char::is_uppercase);
+    }
+
+    match ast::parse::Parser::new().parse(pattern) {
+        Ok(ast) => ast::visit(&ast, UppercaseFinder).unwrap_or(true),
+        Err(_) => false,
+    }
+}
+
+struct UppercaseFinder;
+
+impl Visitor for UppercaseFinder {
+    type Output = bool;
+    type Err = ();
+
+    fn finish(self) -> Result<bool, ()> {
+        Ok(false)
+    }
+
+    /// Stops the walk (with an "error") as soon as an uppercase literal is found.
+    fn visit_pre(&mut self, ast: &Ast) -> Result<(), ()> {
+        match ast {
+            Ast::Literal(literal) if is_uppercase_literal(literal) => Err(()),
+            _ => Ok(()),
+        }
+    }
+
+    fn visit_class_set_item_pre(&mut self, item: &ClassSetItem) -> Result<(), ()> {
+        let uppercase = match item {
+            ClassSetItem::Literal(literal) => is_uppercase_literal(literal),
+            ClassSetItem::Range(range) => {
+                is_uppercase_literal(&range.start) || is_uppercase_literal(&range.end)
+            }
+            _ => false,
+        };
+
+        if uppercase {
+            Err(())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn is_uppercase_literal(literal: &ast::Literal) -> bool {
+    literal.kind == LiteralKind::Verbatim && literal.c.is_uppercase()
+}
+
+/// Set by `-w` and `-x`: where an occurrence has to start and end. `-x`
+/// wins over `-w` when both are given, like in grep.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Boundary {
+    /// Anywhere in the line.
+    None,
+    /// Not preceded nor followed by a word character (Unicode-aware).
+    Word,
+    /// The occurrence has to be the whole line.
+    Line,
+}
+
+impl Boundary {
+    fn wrap(self, pattern: &str) -> String {
+        match self {
+            Boundary::None => pattern.to_string(),
+            // Half boundaries, so that a pattern starting or ending with a
+            // non-word character like `-w .foo` still works.
+            Boundary::Word => format!(r"\b{{start-half}}(?:{})\b{{end-half}}", pattern),
+            Boundary::Line => format!("^(?:{})$", pattern),
+        }
+    }
+}
+
+/// The group printed by `--capture`, by number or by name.
+pub enum Capture {
+    Index(usize),
+    Name(String),
+}
+
+impl Capture {
+    pub fn parse(value: &str) -> Capture {
+        match value.parse() {
+            Ok(index) => Capture::Index(index),
+            Err(_) => Capture::Name(value.to_string()),
+        }
+    }
+}
+
+/// An occurrence in a line: its byte offsets, the index of the pattern that
+/// matched (in the order the patterns were given) and, for a regex, what its
+/// groups matched.
+#[derive(Clone)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub pattern: usize,
+    /// The groups of the regex that took part in the match, in order.
+    pub groups: Vec<Group>,
+}
+
+/// What a group of a regex matched, inside an occurrence.
+#[derive(Clone)]
+pub struct Group {
+    /// Starts at 1, 0 being the whole match.
+    pub index: usize,
+    pub name: Option<String>,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Finds the occurrences of the searched patterns in a line. All the patterns
+/// are looked for in one pass over the line, never one after the other.
+pub enum Matcher {
+    /// A single case sensitive plain string.
+    Literal(String),
+    /// Several case sensitive plain strings, looked for all at once with
+    /// Aho-Corasick.
+    Literals(AhoCorasick),
+    /// A regex given with `-r`, or a plain string searched with `-i`. Case
+    /// insensitivity is left to the regex engine, which uses Unicode simple
+    /// case folding, so the line never has to be changed and the byte
+    /// offsets found are the ones of the original text.
+    /// With `-U` the text is a whole file: `^` and `$` match at the start and
+    /// end of every line, and `.` only matches a newline if the pattern
+    /// starts with `(?s)`.
+    Regex(Regex),
+    /// Several regexes (or plain strings searched with `-i`). The set tells
+    /// in one pass which of them match the line, and only those are then run
+    /// to find where.
+    RegexSet(RegexSet, Vec<Regex>),
+}
+
+impl Matcher {
+    pub fn new(
+        patterns: &[String],
+        use_regex: bool,
+        ignore_case: bool,
+        boundary: Boundary,
+        multiline: bool,
+    ) -> Result<Matcher, ConfigError> {
+        let plain = !use_regex && !ignore_case && boundary == Boundary::None;
+        if plain && patterns.len() == 1 {
+            return Ok(Matcher::Literal(patterns[0].clone()));
+        }
+        // An empty string matches between every two characters, which
+        // Aho-Corasick would also report inside multi-byte characters.
+        if plain && patterns.iter().all(|pattern| !pattern.is_empty()) {
+            return match AhoCorasick::builder()
+                .match_kind(MatchKind::LeftmostLongest)
+                .build(patterns)
+            {
+                Ok(automaton) => Ok(Matcher::Literals(automaton)),
+                Err(_) => Err(ConfigError::InvalidRegex),
+            };
+        }
+
+        let patterns: Vec<String> = patterns
+            .iter()
+            .map(|pattern| {
+                if use_regex {
+                    boundary.wrap(pattern)
+                } else {
+                    boundary.wrap(&regex::escape(pattern))
+                }
+            })
+            .collect();
+
+        let mut regexes = Vec::with_capacity(patterns.len());
+        for pattern in &patterns {
+            match RegexBuilder::new(pattern)
+                .case_insensitive(ignore_case)
+                .multi_line(multiline)
+                .crlf(multiline)
+                .build()
+            {
+                Ok(regex) => regexes.push(regex),
+                Err(_) => return Err(ConfigError::InvalidRegex),
+            }
+        }
+
+        if regexes.len() == 1 {
+            return Ok(Matcher::Regex(regexes.remove(0)));
+        }
+        match RegexSetBuilder::new(&patterns)
+            .case_insensitive(ignore_case)
+            .multi_line(multiline)
+            .crlf(multiline)
+            .build()
+        {
+            Ok(set) => Ok(Matcher::RegexSet(set, regexes)),
+            Err(_) => Err(ConfigError::InvalidRegex),
+        }
+    }
+
+    /// The spans of every occurrence in `line`, in order. When the patterns
+    /// of a set overlap, the one starting first wins, then the longest one.
+    pub fn find_spans(&self, line: &str) -> Vec<Span> {
+        let mut spans = Vec::new();
+        match self {
+            Matcher::Literal(word) => {
+                for (s, _) in line.match_indices(word.as_str()) {
+                    spans.push(Span {
+                        start: s,
+                        end: s + word.len(),
+                        pattern: 0,
+                        groups: Vec::new(),
+                    });
+                }
+            }
+            Matcher::Literals(automaton) => {
+                for found in automaton.find_iter(line) {
+                    spans.push(Span {
+                        start: found.start(),
+                        end: found.end(),
+                        pattern: found.pattern().as_usize(),
+                        groups: Vec::new(),
+                    });
+                }
+            }
+            Matcher::Regex(regex) => {
+                push_regex_spans(&mut spans, regex, line, 0);
+            }
+            Matcher::RegexSet(set, regexes) => {
+                let mut found = Vec::new();
+                for pattern in set.matches(line).iter() {
+                    push_regex_spans(&mut found, &regexes[pattern], line, pattern);
+                }
+                found.sort_by_key(|span| (span.start, usize::MAX - span.end, span.pattern));
+
+                let mut last_end = None;
+                for span in found {
+                    // An empty match right after another one is dropped,
+                    // like a single regex does.
+                    let overlaps = last_end.is_some_and(|end| {
+                        span.start < end || (span.start == end && span.start == span.end)
+                    });
+                    if overlaps {
+                        continue;
+                    }
+                    last_end = Some(span.end);
+                    spans.push(span);
+                }
+            }
+        }
+        spans
+    }
+
+    /// Whether at least one of the patterns has the group.
+    pub fn has_group(&self, capture: &Capture) -> bool {
+        let regexes = match self {
+            Matcher::Literal(_) | Matcher::Literals(_) => return false,
+            Matcher::Regex(regex) => std::slice::from_ref(regex),
+            Matcher::RegexSet(_, regexes) => regexes.as_slice(),
+        };
+
+        regexes.iter().any(|regex| match capture {
+            Capture::Index(index) => *index < regex.captures_len(),
+            Capture::Name(name) => regex.capture_names().flatten().any(|n| n == name),
+        })
+    }
+
+    /// Appends `template` to `dst` for the occurrence of `pattern` found at
+    /// `start`, with `$1` or `${name}` standing for what the groups of the
+    /// pattern matched. Plain strings have no groups, their template is used
+    /// as is.
+    pub fn expand(
+        &self,
+        line: &str,
+        start: usize,
+        pattern: usize,
+        template: &str,
+        dst: &mut String,
+    ) {
+        let regex = match self {
+            Matcher::Literal(_) | Matcher::Literals(_) => None,
+            Matcher::Regex(regex) => Some(regex),
+            Matcher::RegexSet(_, regexes) => Some(&regexes[pattern]),
+        };
+
+        match regex.and_then(|regex| regex.captures_at(line, start)) {
+            Some(caps) if caps.get(0).is_some_and(|found| found.start() == start) => {
+                caps.expand(template, dst)
+            }
+            _ => dst.push_str(template),
+        }
+    }
+}
+
+/// One span per match of the regex, with the groups that took part in it.
+fn push_regex_spans(spans: &mut Vec<Span>, regex: &Regex, line: &str, pattern: usize) {
+    if regex.captures_len() == 1 {
+        // No groups, no need for the slower captures search.
+        for found in regex.find_iter(line) {
+            spans.push(Span {
+                start: found.start(),
+                end: found.end(),
+                pattern,
+                groups: Vec::new(),
+            });
+        }
+        return;
+    }
+
+    for caps in regex.captures_iter(line) {
+        let Some(whole) = caps.get(0) else {
+            continue;
+        };
+        let groups = caps
+            .iter()
+            .zip(regex.capture_names())
+            .enumerate()
+            .skip(1)
+            .filter_map(|(index, (group, name))| {
+                group.map(|group| Group {
+                    index,
+                    name: name.map(String::from),
+                    start: group.start(),
+                    end: group.end(),
+                })
+            })
+            .collect();
+
+        spans.push(Span {
+            start: whole.start(),
+            end: whole.end(),
+            pattern,
+            groups,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(start, end, pattern)` of every span found in `line`.
+    fn spans(
+        patterns: &[&str],
+        use_regex: bool,
+        ignore_case: bool,
+        line: &str,
+    ) -> Vec<(usize, usize, usize)> {
+        let patterns: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+        let matcher =
+            Matcher::new(&patterns, use_regex, ignore_case, Boundary::None, false).unwrap();
+        matcher
+            .find_spans(line)
+            .iter()
+            .map(|span| (span.start, span.end, span.pattern))
+            .collect()
+    }
+
+    #[test]
+    fn smart_case_only_counts_letters_written_as_they_are() {
+        assert!(!has_uppercase_literal("foo", false));
+        assert!(has_uppercase_literal("Foo", false));
+        assert!(has_uppercase_literal(r"\W", false));
+        assert!(!has_uppercase_literal(r"\W+foo", true));
+        assert!(!has_uppercase_literal(r"\p{Lu}", true));
+        assert!(!has_uppercase_literal(r"\x41", true));
+        assert!(has_uppercase_literal("[A-Z]", true));
+        assert!(has_uppercase_literal("(?i)Foo", true));
+        assert!(has_uppercase_literal(r"\.Foo", true));
+    }
+
+    #[test]
+    fn overlapping_patterns_keep_the_first_then_the_longest() {
+        // Aho-Corasick, a set of case insensitive strings and a set of regexes.
+        assert_eq!(
+            spans(&["foo", "foobar"], false, false, "foobar"),
+            [(0, 6, 1)]
+        );
+        assert_eq!(
+            spans(&["foo", "foobar"], false, true, "FOOBAR"),
+            [(0, 6, 1)]
+        );
+        assert_eq!(spans(&["bar", "oba"], true, false, "foobar"), [(2, 5, 1)]);
+        assert_eq!(
+            spans(&["o+", "b"], true, false, "foobar boo"),
+            [(1, 3, 0), (3, 4, 1), (7, 8, 1), (8, 10, 0)]
+        );
+    }
+
+    #[test]
+    fn patterns_matching_the_same_text_keep_the_first_given() {
+        assert_eq!(spans(&["fo+", "f.o"], true, false, "foo"), [(0, 3, 0)]);
+        assert_eq!(spans(&["f.o", "fo+"], true, false, "foo"), [(0, 3, 0)]);
+    }
+
+    #[test]
+    fn an_empty_match_right_after_another_is_dropped() {
+        assert_eq!(spans(&["", "a"], true, false, "ab"), [(0, 1, 1), (2, 2, 0)]);
+        // Where a single regex finds its occurrences.
+        let positions = |spans: Vec<(usize, usize, usize)>| -> Vec<(usize, usize)> {
+            spans.iter().map(|&(start, end, _)| (start, end)).collect()
+        };
+        assert_eq!(
+            positions(spans(&["x*", "a"], true, false, "ab")),
+            positions(spans(&["a|x*"], true, false, "ab"))
+        );
+    }
+}
@@ -0,0 +1,634 @@
+use crate::json;
+use crate::replace;
+use crate::searcher::{ContextLine, FileResult, Occurrence};
+use crate::{BinaryMode, ColumnUnit, Config, FileListing, OutputFormat, SearchState};
+use colored::*;
+use std::fmt::Write as _;
+use std::io::{self, Write as _};
+use std::path::Path;
+
+/// The colors of the groups of a regex in a match, by group number. The rest
+/// of the match stays blue.
+const GROUP_COLORS: [Color; 5] = [
+    Color::Yellow,
+    Color::Green,
+    Color::Cyan,
+    Color::Magenta,
+    Color::Red,
+];
+
+/// Prints everything found in one file. The output is built up front and
+/// written with stdout locked, so files searched in parallel never end up
+/// with their lines interleaved.
+pub fn print_file_result(path: &Path, result: FileResult, config: &Config, state: &SearchState) {
+    if let Some(listing) = config.list_files {
+        print_file_listing(path, &result, listing, config, state);
+        return;
+    }
+
+    if result.occurrences.is_empty() || !state.take_file() {
+        return;
+    }
+    state.found(result.occurrences.len());
+    if config.quiet {
+        return;
+    }
+
+    let file_name_str = display_path(path, config);
+    let mut out = String::new();
+
+    // Binary files are never edited.
+    if config.diff && !result.binary {
+        let occurrences: Vec<&Occurrence> = result.occurrences.iter().collect();
+        let edits = replace::line_edits(&occurrences, config);
+        if let Err(err) = replace::write_diff(&mut out, path, &file_name_str, &edits) {
+            state.warn(err);
+        }
+        write_out(&out);
+    }
+    if config.write && !result.binary {
+        state.edit(path, result.occurrences.clone());
+    }
+    if config.diff || config.interactive {
+        return;
+    }
+
+    match config.output {
+        OutputFormat::Standard => {}
+        OutputFormat::Grep => {
+            write_grep_style(&mut out, &file_name_str, &result, config);
+            write_out(&out);
+            return;
+        }
+        OutputFormat::Json => {
+            json::write_file(&mut out, &file_name_str, &result, config);
+            write_out(&out);
+            return;
+        }
+        OutputFormat::Vimgrep => {
+            write_vimgrep_style(&mut out, &file_name_str, &result, config);
+            write_out(&out);
+            return;
+        }
+    }
+
+    if result.binary && config.binary_mode == BinaryMode::Report {
+        let first = "Binary file ".bold().green();
+        let second = " matches\n".bold().green();
+        let _ = writeln!(out, "{}\"{}\"{}", first, file_name_str, second);
+        write_out(&out);
+        return;
+    }
+
+    let several = config.patterns.len() > 1;
+    let first = "The file ".bold().green();
+    let (second, third) = if config.invert {
+        (
+            if several {
+                " has lines without any of the strings "
+            } else {
+                " has lines without the string "
+            }
+            .bold()
+            .green(),
+            if config.ignore_case {
+                " (ignore case is on)".green()
+            } else {
+                "".green()
+            },
+        )
+    } else if config.use_regex {
+        (
+            if several {
+                " contains substrings that the Regexes "
+            } else {
+                " contains a substring that the Regex "
+            }
+            .bold()
+            .green(),
+            match (several, config.ignore_case) {
+                (false, false) => " has found".green(),
+                (false, true) => " has found (ignore case is on)".green(),
+                (true, false) => " have found".green(),
+                (true, true) => " have found (ignore case is on)".green(),
+            },
+        )
+    } else {
+        (
+            if several {
+                " contains one of the strings "
+            } else {
+                " contains the string "
+            }
+            .bold()
+            .green(),
+            if config.ignore_case {
+                " (ignore case is on)".green()
+            } else {
+                "".green()
+            },
+        )
+    };
+
+    let _ = writeln!(
+        out,
+        "{}\"{}\"{}{}{}\n",
+        first,
+        file_name_str,
+        second,
+        quoted_patterns(&config.patterns),
+        third
+    );
+
+    let num_occurrences = result.occurrences.len();
+    let number_of_occurrences = "Number of occurrences: ".red();
+
+    if !config.only_count {
+        let _ = writeln!(out, "{}{}\n", number_of_occurrences, num_occurrences);
+        write_lines(&mut out, &result, config, None);
+    } else {
+        let _ = writeln!(out, "{}{}", number_of_occurrences, num_occurrences);
+    }
+
+    let line_left = "\nLines left to search: ".red();
+    if let Some(value) = state.lines_left() {
+        let _ = writeln!(out, "{}{}\n", line_left, value);
+    } else {
+        let _ = writeln!(out, "{}Inf\n", line_left);
+    }
+
+    write_out(&out);
+}
+
+/// `-l` and `-L`: prints the path of the file on its own line, if it's one
+/// of those listed. With `-0` the path ends with a NUL byte instead, and is
+/// never colored, so it can be fed as is to `xargs -0`.
+fn print_file_listing(
+    path: &Path,
+    result: &FileResult,
+    listing: FileListing,
+    config: &Config,
+    state: &SearchState,
+) {
+    let matched = !result.occurrences.is_empty();
+    let skipped_binary = result.binary && config.binary_mode == BinaryMode::Skip;
+    let listed = match listing {
+        FileListing::WithMatches => matched,
+        FileListing::WithoutMatch => !matched && !skipped_binary,
+    };
+    if !listed || !state.take_file() {
+        return;
+    }
+    state.found(result.occurrences.len());
+    if config.quiet {
+        return;
+    }
+
+    let path = display_path(path, config);
+    let mut out = String::new();
+    if config.output == OutputFormat::Json {
+        json::write_file_listing(&mut out, &path, matched);
+    } else if config.null_separator {
+        out.push_str(&path);
+        out.push('\0');
+    } else {
+        let _ = writeln!(out, "{}", path.magenta());
+    }
+    write_out(&out);
+}
+
+/// The patterns between double quotes, separated by commas.
+pub fn quoted_patterns(patterns: &[String]) -> String {
+    patterns
+        .iter()
+        .map(|pattern| format!("\"{}\"", pattern))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The path relative to the searched folder, or the absolute one with
+/// `--absolute`.
+pub fn display_path(path: &Path, config: &Config) -> String {
+    if config.absolute_paths {
+        if let Ok(absolute) = std::path::absolute(path) {
+            return absolute.to_string_lossy().into_owned();
+        }
+    }
+
+    match path.strip_prefix(&config.folder_name) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative.to_string_lossy().into_owned(),
+        _ => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// `--no-heading` output: no header, every line starts with the path, e.g.
+/// `src/main.rs:12:text` for an occurrence and `src/main.rs-11-text` for
+/// context.
+fn write_grep_style(out: &mut String, path: &str, result: &FileResult, config: &Config) {
+    if result.binary && config.binary_mode == BinaryMode::Report {
+        let _ = writeln!(out, "Binary file {} matches", path);
+    } else if config.only_count {
+        let _ = writeln!(out, "{}:{}", path.magenta(), result.occurrences.len());
+    } else {
+        write_lines(out, result, config, Some(path));
+    }
+}
+
+/// `--vimgrep` output: `src/main.rs:12:5:text` for every occurrence, so a
+/// line with several of them is printed once per occurrence. Context lines
+/// are left out, an editor couldn't tell them apart.
+fn write_vimgrep_style(out: &mut String, path: &str, result: &FileResult, config: &Config) {
+    if (result.binary && config.binary_mode == BinaryMode::Report) || config.only_count {
+        write_grep_style(out, path, result, config);
+        return;
+    }
+
+    let vimgrep_column = |occurrence: &Occurrence| match config.column {
+        Some(ColumnUnit::Bytes) => occurrence.byte_column(),
+        _ => occurrence.column(),
+    };
+
+    if config.only_matching {
+        for occurrence in extracted(result, config) {
+            // With `-U` only the first line of the match.
+            let text = occurrence.line[occurrence.start..occurrence.end]
+                .split('\n')
+                .next()
+                .unwrap_or_default();
+            let column = vimgrep_column(&occurrence);
+            let (line_number, _) = occurrence.line_range();
+            let _ = writeln!(out, "{}:{}:{}:{}", path, line_number, column, text);
+        }
+        return;
+    }
+
+    for same_line in result
+        .occurrences
+        .chunk_by(|a, b| a.line_number == b.line_number)
+    {
+        let replaced = config
+            .replace
+            .as_ref()
+            .map(|_| replace::replace_line(&same_line[0].line, same_line, config).0);
+
+        for occurrence in same_line {
+            let text = replaced
+                .as_deref()
+                .unwrap_or_else(|| occurrence.first_line());
+            let column = vimgrep_column(occurrence);
+            let (line_number, _) = occurrence.line_range();
+            let _ = writeln!(out, "{}:{}:{}:{}", path, line_number, column, text);
+        }
+    }
+}
+
+/// Writes the occurrences and the context lines around them in line order.
+/// The standard output has a line per occurrence, each with its own
+/// location. With a `path`, grep style, every line is prefixed with it and a
+/// line with several occurrences is printed once, with all of them
+/// highlighted (so a column or offset is the first one's). With `--replace`
+/// a line is always printed once, with all its occurrences replaced. Context
+/// lines are marked with a `-` after their number, and groups of lines that
+/// aren't next to each other are separated by `--`.
+fn write_lines(out: &mut String, result: &FileResult, config: &Config, path: Option<&str>) {
+    if config.only_matching {
+        write_only_matching(out, result, config, path);
+        return;
+    }
+
+    let has_context = config.before_context > 0 || config.after_context > 0;
+    let mut last_line_number = None;
+
+    for printed_line in lines_in_order(result) {
+        let (first, last) = printed_line.line_range();
+        if let Some(last) = last_line_number {
+            if has_context && first > last + 1 {
+                let _ = writeln!(out, "{}", "--".cyan());
+            }
+        }
+        last_line_number = Some(last);
+
+        match printed_line {
+            PrintedLine::Matched(same_line) if config.replace.is_some() => {
+                write_replaced_line(out, same_line, config, path)
+            }
+            PrintedLine::Matched(same_line) if path.is_none() => {
+                for occurrence in same_line {
+                    let lines = occurrence.line_range();
+                    write_highlighted(out, std::slice::from_ref(occurrence), lines, config, path);
+                }
+            }
+            PrintedLine::Matched(same_line) => {
+                write_highlighted(out, same_line, (first, last), config, path)
+            }
+            PrintedLine::Context(context_line) => {
+                write_context_line(out, context_line, config, path)
+            }
+        }
+    }
+}
+
+/// `-o` and `--capture`: only the matched text (or the group) of every
+/// occurrence, one per line. Context lines are left out.
+fn write_only_matching(out: &mut String, result: &FileResult, config: &Config, path: Option<&str>) {
+    for occurrence in extracted(result, config) {
+        let lines = occurrence.line_range();
+        write_highlighted(out, std::slice::from_ref(&occurrence), lines, config, path);
+    }
+}
+
+/// The occurrences narrowed down to what `-o` prints: the whole matches, or
+/// the group of `--capture`. Empty ones, and those where the group didn't
+/// take part in the match, are left out.
+fn extracted(result: &FileResult, config: &Config) -> Vec<Occurrence> {
+    let mut extracted = Vec::new();
+    for occurrence in &result.occurrences {
+        let span = match &config.capture {
+            Some(capture) => match occurrence.group(capture) {
+                Some(span) => span,
+                None => continue,
+            },
+            None => (occurrence.start, occurrence.end),
+        };
+
+        if span.0 < span.1 {
+            extracted.push(Occurrence {
+                start: span.0,
+                end: span.1,
+                groups: Vec::new(),
+                ..occurrence.clone()
+            });
+        }
+    }
+    extracted
+}
+
+pub enum PrintedLine<'a> {
+    /// A matched line and all its occurrences. With `-U` the "line" can be
+    /// several ones.
+    Matched(&'a [Occurrence]),
+    Context(&'a ContextLine),
+}
+
+impl PrintedLine<'_> {
+    /// The first and last lines printed, only different with `-U`.
+    pub fn line_range(&self) -> (usize, usize) {
+        match self {
+            PrintedLine::Matched(same_line) => {
+                let first = &same_line[0];
+                (first.line_number, first.last_line_number)
+            }
+            PrintedLine::Context(context_line) => {
+                (context_line.line_number, context_line.line_number)
+            }
+        }
+    }
+}
+
+/// The matched lines and the context lines of a file, merged in line order.
+pub fn lines_in_order(result: &FileResult) -> Vec<PrintedLine<'_>> {
+    let mut lines = Vec::new();
+    let mut context = result.context.iter().peekable();
+
+    for same_line in result
+        .occurrences
+        .chunk_by(|a, b| a.line_number == b.line_number)
+    {
+        let line_number = same_line[0].line_number;
+        while let Some(context_line) = context.next_if(|c| c.line_number < line_number) {
+            lines.push(PrintedLine::Context(context_line));
+        }
+        lines.push(PrintedLine::Matched(same_line));
+    }
+    lines.extend(context.map(PrintedLine::Context));
+
+    lines
+}
+
+/// Writes the lines `first` to `last` of the text the occurrences share,
+/// each after its location, with the occurrences highlighted. With `-o` only
+/// the occurrences are written, not the rest of the lines.
+fn write_highlighted(
+    out: &mut String,
+    occurrences: &[Occurrence],
+    (first, last): (usize, usize),
+    config: &Config,
+    path: Option<&str>,
+) {
+    let line = &occurrences[0].line;
+    let mut from = 0;
+    for _ in occurrences[0].line_number..first {
+        from = line[from..]
+            .find('\n')
+            .map_or(line.len(), |newline| from + newline + 1);
+    }
+
+    for line_number in first..=last {
+        let next = line[from..]
+            .find('\n')
+            .map_or(line.len(), |newline| from + newline);
+        let content = &line[from..next];
+        let to = if next < line.len() {
+            from + content.strip_suffix('\r').unwrap_or(content).len()
+        } else {
+            next
+        };
+
+        if line_number == first {
+            write_location(out, occurrences, config, path);
+        } else {
+            write_next_line_location(out, line_number, path);
+        }
+        let mut written = from;
+        for occurrence in occurrences {
+            if occurrence.end < from {
+                continue;
+            }
+            if occurrence.start > to {
+                break;
+            }
+            if !config.only_matching {
+                out.push_str(&line[written..occurrence.start.clamp(written, to)]);
+            }
+            write_match(out, occurrence, from, to);
+            written = occurrence.end.clamp(written, to);
+        }
+        if !config.only_matching {
+            out.push_str(&line[written..to]);
+        }
+        out.push('\n');
+
+        from = next + 1;
+    }
+}
+
+/// The part of the matched text of an occurrence between `from` and `to`,
+/// with what each group matched in its own color. Where groups are nested
+/// the innermost one shows.
+fn write_match(out: &mut String, occurrence: &Occurrence, from: usize, to: usize) {
+    let mut bounds = vec![occurrence.start, occurrence.end];
+    for group in &occurrence.groups {
+        bounds.extend([group.start, group.end]);
+    }
+    for bound in &mut bounds {
+        *bound = (*bound).clamp(from, to);
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    for piece in bounds.windows(2) {
+        let (start, end) = (piece[0], piece[1]);
+        let text = &occurrence.line[start..end];
+        // A group nested in another one comes after it.
+        let innermost = occurrence
+            .groups
+            .iter()
+            .rev()
+            .find(|group| group.start <= start && end <= group.end);
+        let _ = match innermost {
+            Some(group) => {
+                let color = GROUP_COLORS[(group.index - 1) % GROUP_COLORS.len()];
+                write!(out, "{}", text.color(color))
+            }
+            None => write!(out, "{}", text.blue()),
+        };
+    }
+}
+
+/// `--replace`: the line with all its occurrences replaced, and the
+/// replacements highlighted.
+fn write_replaced_line(
+    out: &mut String,
+    same_line: &[Occurrence],
+    config: &Config,
+    path: Option<&str>,
+) {
+    write_location(out, same_line, config, path);
+
+    let (line, replaced) = replace::replace_line(&same_line[0].line, same_line, config);
+    let mut last_end = 0;
+    for (start, end) in replaced {
+        let _ = write!(out, "{}{}", &line[last_end..start], line[start..end].blue());
+        last_end = end;
+    }
+    let _ = writeln!(out, "{}", &line[last_end..]);
+}
+
+/// What comes before the text of a matched line. In the standard output,
+/// with several patterns, the line is tagged with the ones that matched in
+/// it: `(12) [foo] text`. `--column` and `--byte-offset` give the ones of
+/// the first occurrence, turning the line number into `(12:5 @310)`, or
+/// adding `path:12:5:310:` grep style.
+fn write_location(
+    out: &mut String,
+    occurrences: &[Occurrence],
+    config: &Config,
+    path: Option<&str>,
+) {
+    let occurrence = &occurrences[0];
+    let (line_number, _) = occurrence.line_range();
+    let column = config.column.map(|unit| match unit {
+        ColumnUnit::Chars => occurrence.column(),
+        ColumnUnit::Bytes => occurrence.byte_column(),
+    });
+    let offset = config.byte_offset.then(|| occurrence.absolute_offset());
+
+    match path {
+        Some(path) => {
+            let mut location = line_number.to_string();
+            if let Some(column) = column {
+                let _ = write!(location, ":{}", column);
+            }
+            if let Some(offset) = offset {
+                let _ = write!(location, ":{}", offset);
+            }
+            let _ = write!(out, "{}:{}:", path.magenta(), location.green());
+        }
+        None => {
+            let _ = write!(out, "({}", line_number);
+            if let Some(column) = column {
+                let _ = write!(out, ":{}", column);
+            }
+            if let Some(offset) = offset {
+                let _ = write!(out, " @{}", offset);
+            }
+            let _ = write!(out, ") ");
+            if config.patterns.len() > 1 && !config.invert {
+                let mut patterns: Vec<usize> = Vec::new();
+                for occurrence in occurrences {
+                    if !patterns.contains(&occurrence.pattern) {
+                        patterns.push(occurrence.pattern);
+                    }
+                }
+                for pattern in patterns {
+                    let pattern = format!("[{}]", config.patterns[pattern]);
+                    let _ = write!(out, "{} ", pattern.yellow());
+                }
+            }
+        }
+    }
+}
+
+/// The location of the next lines of an occurrence spanning several lines
+/// with `-U`: only their number, `(13) ` or `path:13:`.
+fn write_next_line_location(out: &mut String, line_number: usize, path: Option<&str>) {
+    let _ = match path {
+        Some(path) => write!(
+            out,
+            "{}:{}:",
+            path.magenta(),
+            line_number.to_string().green()
+        ),
+        None => write!(out, "({}) ", line_number),
+    };
+}
+
+/// Context lines have no column, but `--byte-offset` gives the offset of
+/// their start: `(11 @290)- text` or `path-11-290-text`.
+fn write_context_line(
+    out: &mut String,
+    context_line: &ContextLine,
+    config: &Config,
+    path: Option<&str>,
+) {
+    let offset = config.byte_offset.then_some(context_line.line_offset);
+
+    let _ = match path {
+        Some(path) => {
+            let mut location = context_line.line_number.to_string();
+            if let Some(offset) = offset {
+                let _ = write!(location, "-{}", offset);
+            }
+            writeln!(
+                out,
+                "{}-{}-{}",
+                path.magenta(),
+                location.green(),
+                context_line.line
+            )
+        }
+        None => {
+            let mut location = context_line.line_number.to_string();
+            if let Some(offset) = offset {
+                let _ = write!(location, " @{}", offset);
+            }
+            writeln!(
+                out,
+                "{}",
+                format!("({})- {}", location, context_line.line).dimmed()
+            )
+        }
+    };
+}
+
+pub fn print_json_summary(state: &SearchState) {
+    let mut out = String::new();
+    json::write_summary(&mut out, state);
+    write_out(&out);
+}
+
+fn write_out(out: &str) {
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(out.as_bytes());
+    let _ = stdout.flush();
+}
@@ -0,0 +1,368 @@
+//! `--replace`, `--diff`, `--write` and `--interactive`. The occurrences
+//! `finds` returns are turned into one edit per matched line. Nothing is
+//! written while the folder is walked: the occurrences to replace are kept in
+//! the search state and the files are written once it's over, so a temporary
+//! or backup file never gets searched.
+
+use crate::searcher::Occurrence;
+use crate::{Config, ConfigError, SearchState};
+use colored::*;
+use std::fmt::Write as _;
+use std::fs::{self, File, Permissions};
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Unchanged lines printed around every change by `--diff`.
+const DIFF_CONTEXT: usize = 3;
+
+/// A matched line and what it becomes once its occurrences are replaced.
+/// With `-U` the "line" can be several ones.
+pub struct LineEdit {
+    pub line_number: usize,
+    /// The number of the last line replaced, only different from
+    /// `line_number` with `-U`.
+    pub last_line_number: usize,
+    /// Byte offset of the start of the line in the file.
+    pub offset: u64,
+    pub old: String,
+    pub new: String,
+}
+
+/// The occurrences of a file that `--write` or `--interactive` will replace.
+pub struct FileEdits {
+    pub path: PathBuf,
+    pub occurrences: Vec<Occurrence>,
+}
+
+/// `line` with the given occurrences in it replaced by the `--replace`
+/// template, and the byte spans of the replacements in the new line.
+pub fn replace_line<'a>(
+    line: &str,
+    occurrences: impl IntoIterator<Item = &'a Occurrence>,
+    config: &Config,
+) -> (String, Vec<(usize, usize)>) {
+    let template = config.replace.as_deref().unwrap_or_default();
+    let mut new = String::with_capacity(line.len());
+    let mut replaced = Vec::new();
+    let mut last_end = 0;
+
+    for occurrence in occurrences {
+        new.push_str(&line[last_end..occurrence.start]);
+        let start = new.len();
+        if config.use_regex {
+            let (start, pattern) = (occurrence.start, occurrence.pattern);
+            config
+                .matcher
+                .expand(line, start, pattern, template, &mut new);
+        } else {
+            new.push_str(template);
+        }
+        replaced.push((start, new.len()));
+        last_end = occurrence.end;
+    }
+
+    new.push_str(&line[last_end..]);
+    (new, replaced)
+}
+
+/// The edits of every line of the occurrences (sorted by line) that actually
+/// changes.
+pub fn line_edits(occurrences: &[&Occurrence], config: &Config) -> Vec<LineEdit> {
+    occurrences
+        .chunk_by(|a, b| a.line_number == b.line_number)
+        .map(|same_line| {
+            let first = &same_line[0];
+            LineEdit {
+                line_number: first.line_number,
+                last_line_number: first.last_line_number,
+                offset: first.line_offset,
+                old: first.line.to_string(),
+                new: replace_line(&first.line, same_line.iter().copied(), config).0,
+            }
+        })
+        .filter(|edit| edit.old != edit.new)
+        .collect()
+}
+
+/// Writes the edits of a file as a unified diff, which `patch -p1` can apply
+/// from the searched folder.
+pub fn write_diff(
+    out: &mut String,
+    path: &Path,
+    display_path: &str,
+    edits: &[LineEdit],
+) -> Result<(), ConfigError> {
+    if edits.is_empty() {
+        return Ok(());
+    }
+
+    let content = read_checked(path, edits)?;
+    // Split on `\n` only, every line keeping its terminator, so that the
+    // `\r` of CRLF lines is in the diff and `patch` finds them.
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+
+    let _ = writeln!(out, "{}", format!("--- a/{}", display_path).bold());
+    let _ = writeln!(out, "{}", format!("+++ b/{}", display_path).bold());
+
+    // Lines added and removed by the replacements of the hunks already
+    // written.
+    let mut added = 0;
+    let mut removed = 0;
+    let mut i = 0;
+    while i < edits.len() {
+        // Changes close enough for their context to touch share a hunk.
+        let mut j = i + 1;
+        while j < edits.len()
+            && edits[j].line_number <= edits[j - 1].last_line_number + 2 * DIFF_CONTEXT + 1
+        {
+            j += 1;
+        }
+        let hunk: Vec<(&LineEdit, &[&str], String)> = edits[i..j]
+            .iter()
+            .map(|edit| {
+                let (old_lines, new) = replaced_lines(&content, &lines, edit);
+                (edit, old_lines, new)
+            })
+            .collect();
+
+        let first = hunk[0].0.line_number.saturating_sub(DIFF_CONTEXT).max(1);
+        let last = (hunk[hunk.len() - 1].0.last_line_number + DIFF_CONTEXT).min(lines.len());
+        let old_count = last - first + 1;
+        let (hunk_added, hunk_removed) =
+            hunk.iter()
+                .fold((0, 0), |(added, removed), (_, old_lines, new)| {
+                    let new_lines = new.split_inclusive('\n').count();
+                    (added + new_lines, removed + old_lines.len())
+                });
+        let new_count = old_count + hunk_added - hunk_removed;
+        // An empty side of a hunk starts at the line before it.
+        let new_first = first + added - removed - usize::from(new_count == 0);
+
+        let header = format!(
+            "@@ -{},{} +{},{} @@",
+            first, old_count, new_first, new_count
+        );
+        let _ = writeln!(out, "{}", header.cyan());
+
+        let mut hunk = hunk.iter().peekable();
+        let mut line_number = first;
+        while line_number <= last {
+            match hunk.next_if(|(edit, _, _)| edit.line_number == line_number) {
+                Some((_, old_lines, new)) => {
+                    for old_line in old_lines.iter() {
+                        write_diff_line(out, '-', old_line, Some(Color::Red));
+                    }
+                    for new_line in new.split_inclusive('\n') {
+                        write_diff_line(out, '+', new_line, Some(Color::Green));
+                    }
+                    line_number += old_lines.len();
+                }
+                None => {
+                    write_diff_line(out, ' ', lines[line_number - 1], None);
+                    line_number += 1;
+                }
+            }
+        }
+
+        added += hunk_added;
+        removed += hunk_removed;
+        i = j;
+    }
+
+    Ok(())
+}
+
+/// The lines of `content` an edit replaces, and the text replacing them: the
+/// new text, followed by what the edit leaves of its last line (its
+/// terminator at least, if it has one).
+fn replaced_lines<'a>(
+    content: &str,
+    lines: &'a [&'a str],
+    edit: &LineEdit,
+) -> (&'a [&'a str], String) {
+    let last = edit.last_line_number.min(lines.len());
+    let old_lines = &lines[edit.line_number - 1..last];
+    let start = edit.offset as usize;
+    let end = start + old_lines.iter().map(|line| line.len()).sum::<usize>();
+    let new = format!("{}{}", edit.new, &content[start + edit.old.len()..end]);
+    (old_lines, new)
+}
+
+/// Writes a line of a diff with its own terminator, `\r\n` or `\n`, and the
+/// marker `patch` expects after a last line without one.
+fn write_diff_line(out: &mut String, sign: char, line: &str, color: Option<Color>) {
+    let text = line.strip_suffix('\n').unwrap_or(line);
+    let text = text.strip_suffix('\r').unwrap_or(text);
+    let terminator = &line[text.len()..];
+
+    let text = format!("{}{}", sign, text);
+    match color {
+        Some(color) => {
+            let _ = write!(out, "{}", text.color(color));
+        }
+        None => out.push_str(&text),
+    }
+    if terminator.ends_with('\n') {
+        out.push_str(terminator);
+    } else {
+        let _ = writeln!(out, "{}\n\\ No newline at end of file", terminator);
+    }
+}
+
+/// Writes the files kept for `--write`, once the search is over. A file that
+/// can't be written is reported and left as it was. Returns how many files
+/// were written.
+pub fn write_files(state: &SearchState, config: &Config) -> usize {
+    let mut written = 0;
+    for file in take_files(state) {
+        let occurrences: Vec<&Occurrence> = file.occurrences.iter().collect();
+        let edits = line_edits(&occurrences, config);
+        match write_file(&file.path, &edits, config.backup) {
+            Ok(()) => written += 1,
+            Err(err) => state.warn(err),
+        }
+    }
+    written
+}
+
+/// The files kept for `--write` or `--interactive`, sorted by path.
+pub fn take_files(state: &SearchState) -> Vec<FileEdits> {
+    let mut files = std::mem::take(&mut *state.edits.lock().unwrap());
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+}
+
+pub fn write_file(path: &Path, edits: &[LineEdit], backup: bool) -> Result<(), ConfigError> {
+    let content = read_checked(path, edits)?;
+    let new_content = apply(&content, edits);
+    let write_error = |err: io::Error| {
+        ConfigError::FileWriteError(path.to_string_lossy().into_owned(), err.to_string())
+    };
+
+    let permissions = fs::metadata(path).map_err(write_error)?.permissions();
+    let temp = sibling(path, |name| {
+        format!(".{}.{}.rgrep-tmp", name, process::id())
+    });
+    if let Err(err) = replace_atomically(path, &temp, &new_content, permissions, backup) {
+        let _ = fs::remove_file(&temp);
+        return Err(write_error(err));
+    }
+    Ok(())
+}
+
+/// Writes the new content next to the file, then renames it over the file,
+/// so that it's never seen half written.
+fn replace_atomically(
+    path: &Path,
+    temp: &Path,
+    content: &str,
+    permissions: Permissions,
+    backup: bool,
+) -> io::Result<()> {
+    let mut file = File::create(temp)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::set_permissions(temp, permissions)?;
+
+    if backup {
+        fs::copy(path, sibling(path, |name| format!("{}.bak", name)))?;
+    }
+    fs::rename(temp, path)
+}
+
+/// A path in the same folder as `path`, named after its file name.
+fn sibling(path: &Path, name: impl Fn(&str) -> String) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(name(&file_name))
+}
+
+/// Reads a file to edit, checking that the lines to replace are still the
+/// ones that were searched.
+fn read_checked(path: &Path, edits: &[LineEdit]) -> Result<String, ConfigError> {
+    let path_str = || path.to_string_lossy().into_owned();
+    let content = fs::read_to_string(path)
+        .map_err(|err| ConfigError::FileReadError(path_str(), err.to_string()))?;
+
+    for edit in edits {
+        let start = edit.offset as usize;
+        if content.get(start..start + edit.old.len()) != Some(edit.old.as_str()) {
+            return Err(ConfigError::FileWriteError(
+                path_str(),
+                "it changed since it was searched".to_string(),
+            ));
+        }
+    }
+    Ok(content)
+}
+
+fn apply(content: &str, edits: &[LineEdit]) -> String {
+    let mut new_content = String::with_capacity(content.len());
+    let mut last_end = 0;
+    for edit in edits {
+        let start = edit.offset as usize;
+        new_content.push_str(&content[last_end..start]);
+        new_content.push_str(&edit.new);
+        last_end = start + edit.old.len();
+    }
+    new_content.push_str(&content[last_end..]);
+    new_content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The diff of the edits of a file with the given content.
+    fn diff(name: &str, content: &str, edits: &[LineEdit]) -> String {
+        colored::control::set_override(false);
+        let path = std::env::temp_dir().join(format!("rgrep-{}-{}", process::id(), name));
+        fs::write(&path, content).unwrap();
+        let mut out = String::new();
+        let result = write_diff(&mut out, &path, "f", edits);
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+        out
+    }
+
+    fn edit(lines: (usize, usize), offset: u64, old: &str, new: &str) -> LineEdit {
+        LineEdit {
+            line_number: lines.0,
+            last_line_number: lines.1,
+            offset,
+            old: old.to_string(),
+            new: new.to_string(),
+        }
+    }
+
+    #[test]
+    fn diff_removing_every_line() {
+        let out = diff("empty", "\n\n", &[edit((1, 2), 0, "\n\n", "")]);
+        assert_eq!(out, "--- a/f\n+++ b/f\n@@ -1,2 +0,0 @@\n-\n-\n");
+    }
+
+    #[test]
+    fn diff_of_an_edit_taking_in_the_last_newline() {
+        let out = diff("last", "a\nfoo\n", &[edit((2, 2), 2, "foo\n", "bar")]);
+        assert_eq!(
+            out,
+            "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n a\n-foo\n+bar\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn diff_of_an_edit_keeping_the_last_newline() {
+        let out = diff("kept", "foo\nb\n", &[edit((1, 2), 0, "foo\nb\n", "bar\n")]);
+        assert_eq!(out, "--- a/f\n+++ b/f\n@@ -1,2 +1,1 @@\n-foo\n-b\n+bar\n");
+    }
+
+    #[test]
+    fn diff_of_an_edit_taking_in_the_newline_before_a_blank_line() {
+        let content = "use a;\n\nfn main() {}\n";
+        let out = diff("blank", content, &[edit((1, 2), 0, "use a;\n", "X")]);
+        assert_eq!(
+            out,
+            "--- a/f\n+++ b/f\n@@ -1,3 +1,2 @@\n-use a;\n-\n+X\n fn main() {}\n"
+        );
+    }
+}
@@ -0,0 +1,752 @@
+use crate::matcher::{Capture, Group, Span};
+use crate::{BinaryMode, Config, ConfigError, SearchState};
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+/// How many bytes from the start of a file are looked at to decide if it's binary.
+const BINARY_DETECTION_BLOCK: usize = 8 * 1024;
+
+/// Size of the chunks a file is read in when it isn't memory-mapped.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Files at least this big are memory-mapped when `--mmap` is on.
+const MMAP_THRESHOLD: u64 = 1024 * 1024;
+
+#[derive(Clone)]
+pub struct Occurrence {
+    /// With `-U`, `line` holds every line of the occurrences whose lines
+    /// touch, and this is the number of the first one.
+    pub line_number: usize,
+    /// The number of the last line of `line`, only different from
+    /// `line_number` with `-U`.
+    pub last_line_number: usize,
+    /// Byte offset of the start of the line in the file.
+    pub line_offset: u64,
+    /// Shared by all the occurrences of the line, which is only stored once
+    /// however many there are in it.
+    pub line: Arc<LineText>,
+    pub start: usize,
+    pub end: usize,
+    /// Index of the pattern that matched, in the order they were given.
+    pub pattern: usize,
+    /// What the groups of a regex matched, empty for a plain string.
+    pub groups: Vec<Group>,
+}
+
+impl Occurrence {
+    /// 1-based column of the start of the occurrence, in characters.
+    pub fn column(&self) -> usize {
+        self.line[self.start_of_line()..self.start].chars().count() + 1
+    }
+
+    /// 1-based column of the start of the occurrence, in bytes of the file.
+    pub fn byte_column(&self) -> usize {
+        self.line.raw_offset(self.start) - self.line.raw_offset(self.start_of_line()) + 1
+    }
+
+    /// The numbers of the first and last lines the occurrence is on, only
+    /// different with `-U`. A newline ending the occurrence doesn't count.
+    pub fn line_range(&self) -> (usize, usize) {
+        let newlines =
+            |end: usize| memchr::memchr_iter(b'\n', &self.line.as_bytes()[..end]).count();
+        let last = if self.end > self.start {
+            self.end - 1
+        } else {
+            self.start
+        };
+        (
+            self.line_number + newlines(self.start),
+            self.line_number + newlines(last),
+        )
+    }
+
+    /// Where the line the occurrence starts on starts in `line`.
+    fn start_of_line(&self) -> usize {
+        self.line[..self.start]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1)
+    }
+
+    /// The text of the line the occurrence starts on, the whole `line`
+    /// unless the occurrence spans several with `-U`.
+    pub fn first_line(&self) -> &str {
+        let rest = &self.line[self.start_of_line()..];
+        match rest.split_once('\n') {
+            Some((line, _)) => line.strip_suffix('\r').unwrap_or(line),
+            None => rest,
+        }
+    }
+
+    /// Byte offset of the start of the occurrence in the file.
+    pub fn absolute_offset(&self) -> u64 {
+        self.line_offset + self.line.raw_offset(self.start) as u64
+    }
+
+    /// The byte span of a group in the line, group 0 being the whole
+    /// occurrence. None when the pattern has no such group or it took no part
+    /// in the match.
+    pub fn group(&self, capture: &Capture) -> Option<(usize, usize)> {
+        if let Capture::Index(0) = capture {
+            return Some((self.start, self.end));
+        }
+
+        self.groups
+            .iter()
+            .find(|group| match capture {
+                Capture::Index(index) => group.index == *index,
+                Capture::Name(name) => group.name.as_deref() == Some(name.as_str()),
+            })
+            .map(|group| (group.start, group.end))
+    }
+}
+
+/// The text of a matched line. With `--binary text` and in binary files,
+/// invalid UTF-8 is decoded as U+FFFD, which doesn't take as many bytes as
+/// what it replaces, so offsets in the text aren't offsets in the file.
+pub struct LineText {
+    text: String,
+    /// See `invalid_utf8`, empty when the line is valid UTF-8.
+    invalid: Vec<(usize, usize)>,
+}
+
+impl LineText {
+    /// `text` decoded lossily from `bytes`.
+    fn decoded(text: String, bytes: &[u8]) -> LineText {
+        let invalid = if text.as_bytes() == bytes {
+            Vec::new()
+        } else {
+            invalid_utf8(bytes)
+        };
+        LineText { text, invalid }
+    }
+
+    /// The offset in the bytes of the line of an offset in its text.
+    pub fn raw_offset(&self, offset: usize) -> usize {
+        raw_offset(&self.invalid, offset)
+    }
+}
+
+impl Deref for LineText {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+/// Where each U+FFFD that lossy decoding puts in place of invalid UTF-8 ends
+/// in the text, and where the bytes it replaced end.
+fn invalid_utf8(bytes: &[u8]) -> Vec<(usize, usize)> {
+    let mut invalid = Vec::new();
+    let (mut text_end, mut raw_end) = (0, 0);
+    for chunk in bytes.utf8_chunks() {
+        text_end += chunk.valid().len();
+        raw_end += chunk.valid().len();
+        if !chunk.invalid().is_empty() {
+            text_end += char::REPLACEMENT_CHARACTER.len_utf8();
+            raw_end += chunk.invalid().len();
+            invalid.push((text_end, raw_end));
+        }
+    }
+    invalid
+}
+
+/// The offset in the bytes a text was decoded from of an offset in the text.
+fn raw_offset(invalid: &[(usize, usize)], offset: usize) -> usize {
+    let before = invalid.partition_point(|&(text_end, _)| text_end <= offset);
+    match before.checked_sub(1).map(|last| invalid[last]) {
+        Some((text_end, raw_end)) => raw_end + offset - text_end,
+        None => offset,
+    }
+}
+
+/// A line printed around the occurrences because of `-A`, `-B` or `-C`.
+pub struct ContextLine {
+    pub line_number: usize,
+    pub line_offset: u64,
+    pub line: String,
+}
+
+pub struct FileResult {
+    pub binary: bool,
+    pub lines_searched: usize,
+    pub occurrences: Vec<Occurrence>,
+    /// Never contains a line that also has occurrences, so a line is only
+    /// printed once even when the context of two occurrences overlaps.
+    pub context: Vec<ContextLine>,
+}
+
+/// Searches a file line by line, without ever holding more than one line of it
+/// in memory (unless it's memory-mapped). Every line looked at is taken out of
+/// the shared line budget and the search stops once it runs out.
+pub fn finds(path: &Path, config: &Config, state: &SearchState) -> Result<FileResult, ConfigError> {
+    let file = File::open(path).map_err(|err| read_error(path, err))?;
+
+    if config.mmap {
+        if let Ok(metadata) = file.metadata() {
+            if metadata.is_file() && metadata.len() >= MMAP_THRESHOLD {
+                // SAFETY: the map is only read while it's alive. If another
+                // process truncates the file meanwhile we may get a SIGBUS,
+                // which is the usual trade-off of searching mapped files.
+                if let Ok(map) = unsafe { Mmap::map(&file) } {
+                    if config.multiline {
+                        return search_multiline(&map, path, config, state);
+                    }
+                    return search_slice(&map, path, config, state);
+                }
+            }
+        }
+    }
+
+    let mut reader = BufReader::with_capacity(READ_BUFFER_SIZE, file);
+    if config.multiline {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|err| read_error(path, err))?;
+        return search_multiline(&bytes, path, config, state);
+    }
+
+    let first_block = reader.fill_buf().map_err(|err| read_error(path, err))?;
+
+    let mut searcher =
+        LineSearcher::new(path, config, check_binary(first_block, path, config, state));
+    if searcher.skips_file() {
+        return Ok(searcher.finish());
+    }
+
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|err| read_error(path, err))?;
+        if read == 0 || !searcher.line(&line, state)? {
+            break;
+        }
+    }
+
+    Ok(searcher.finish())
+}
+
+fn search_slice(
+    bytes: &[u8],
+    path: &Path,
+    config: &Config,
+    state: &SearchState,
+) -> Result<FileResult, ConfigError> {
+    let mut searcher = LineSearcher::new(path, config, check_binary(bytes, path, config, state));
+    if searcher.skips_file() {
+        return Ok(searcher.finish());
+    }
+
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let end = match memchr::memchr(b'\n', rest) {
+            Some(position) => position + 1,
+            None => rest.len(),
+        };
+
+        if !searcher.line(&rest[..end], state)? {
+            break;
+        }
+        rest = &rest[end..];
+    }
+
+    Ok(searcher.finish())
+}
+
+/// `-U`: the file is searched as a whole, so that an occurrence can span
+/// several lines. The occurrences whose lines touch share their lines, as if
+/// they were all on one long line, so they're replaced together.
+fn search_multiline(
+    bytes: &[u8],
+    path: &Path,
+    config: &Config,
+    state: &SearchState,
+) -> Result<FileResult, ConfigError> {
+    let binary = check_binary(bytes, path, config, state);
+    let mut result = FileResult {
+        binary,
+        lines_searched: 0,
+        occurrences: Vec::new(),
+        context: Vec::new(),
+    };
+    if binary && config.binary_mode == BinaryMode::Skip {
+        return Ok(result);
+    }
+
+    let bytes = &bytes[..taken_lines_end(bytes, config, state)];
+    let text = if binary || config.binary_mode == BinaryMode::Text {
+        String::from_utf8_lossy(bytes)
+    } else {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Cow::Borrowed(text),
+            // Invalid UTF-8 past the block binary files are detected with:
+            // the file is binary from the line it's on.
+            Err(err) => {
+                result.binary = true;
+                if config.binary_mode == BinaryMode::Skip {
+                    let valid = &bytes[..err.valid_up_to()];
+                    let end = memchr::memrchr(b'\n', valid).map_or(0, |newline| newline + 1);
+                    let line_number = memchr::memchr_iter(b'\n', &valid[..end]).count() + 1;
+                    state.warn(not_utf8_from_line(path, line_number));
+                    String::from_utf8_lossy(&bytes[..end])
+                } else {
+                    String::from_utf8_lossy(bytes)
+                }
+            }
+        }
+    };
+    let invalid = match text {
+        Cow::Owned(_) => invalid_utf8(bytes),
+        Cow::Borrowed(_) => Vec::new(),
+    };
+    let lines = Lines::new(&text, &invalid);
+    result.lines_searched = lines.starts.len();
+    if lines.starts.is_empty() {
+        return Ok(result);
+    }
+
+    let mut spans = config.matcher.find_spans(&text);
+    // An empty match after the last newline isn't on any line.
+    spans.retain(|span| span.start < text.len() || !text.ends_with('\n'));
+    if let Some(max) = config.max_count {
+        spans.truncate(max);
+    }
+    spans.truncate(state.take_matches(spans.len()));
+
+    // The first and last lines (0-based) of the occurrences whose lines
+    // touch, and their spans.
+    let mut blocks: Vec<(usize, usize, Vec<Span>)> = Vec::new();
+    for span in spans {
+        let (first, last) = lines.of(&span);
+        match blocks.last_mut() {
+            Some(block) if first <= block.1 => {
+                block.1 = block.1.max(last);
+                block.2.push(span);
+            }
+            _ => blocks.push((first, last, vec![span])),
+        }
+    }
+
+    // The first line that isn't printed yet.
+    let mut next_line = 0;
+    for (index, (first, last, spans)) in blocks.iter().enumerate() {
+        let before = first.saturating_sub(config.before_context).max(next_line);
+        for line_index in before..*first {
+            result.context.push(lines.context_line(line_index));
+        }
+
+        let start = lines.starts[*first];
+        let end = spans
+            .iter()
+            .map(|span| span.end)
+            .fold(lines.content_end(*last), usize::max);
+        let line = Arc::new(lines.text(start, end));
+        for span in spans {
+            result.occurrences.push(Occurrence {
+                line_number: first + 1,
+                last_line_number: last + 1,
+                line_offset: lines.raw_offset(start) as u64,
+                line: line.clone(),
+                start: span.start - start,
+                end: span.end - start,
+                pattern: span.pattern,
+                groups: span
+                    .groups
+                    .iter()
+                    .map(|group| Group {
+                        start: group.start - start,
+                        end: group.end - start,
+                        ..group.clone()
+                    })
+                    .collect(),
+            });
+        }
+
+        let next_block = blocks
+            .get(index + 1)
+            .map_or(lines.starts.len(), |next| next.0);
+        next_line = (last + 1 + config.after_context).min(next_block);
+        for line_index in last + 1..next_line {
+            result.context.push(lines.context_line(line_index));
+        }
+    }
+
+    Ok(result)
+}
+
+/// Takes the lines of a file searched with `-U` out of the line budgets, and
+/// returns where the last one taken ends.
+fn taken_lines_end(bytes: &[u8], config: &Config, state: &SearchState) -> usize {
+    let mut taken = 0;
+    let mut end = 0;
+    while end < bytes.len() {
+        if config.max_lines_per_file.is_some_and(|max| taken >= max) || !state.take_line() {
+            break;
+        }
+        taken += 1;
+        end = match memchr::memchr(b'\n', &bytes[end..]) {
+            Some(position) => end + position + 1,
+            None => bytes.len(),
+        };
+    }
+    end
+}
+
+/// Where the lines of a file searched with `-U` start.
+struct Lines<'a> {
+    text: &'a str,
+    /// See `invalid_utf8`.
+    invalid: &'a [(usize, usize)],
+    starts: Vec<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str, invalid: &'a [(usize, usize)]) -> Lines<'a> {
+        let mut starts = Vec::new();
+        if !text.is_empty() {
+            starts.push(0);
+        }
+        for newline in memchr::memchr_iter(b'\n', text.as_bytes()) {
+            if newline + 1 < text.len() {
+                starts.push(newline + 1);
+            }
+        }
+        Lines {
+            text,
+            invalid,
+            starts,
+        }
+    }
+
+    /// The offset in the file of an offset in the text.
+    fn raw_offset(&self, offset: usize) -> usize {
+        raw_offset(self.invalid, offset)
+    }
+
+    /// The text between `start` and `end`, which knows where it is in the
+    /// file.
+    fn text(&self, start: usize, end: usize) -> LineText {
+        let raw_start = self.raw_offset(start);
+        let from = self
+            .invalid
+            .partition_point(|&(text_end, _)| text_end <= start);
+        let to = self
+            .invalid
+            .partition_point(|&(text_end, _)| text_end <= end);
+        LineText {
+            text: self.text[start..end].to_string(),
+            invalid: self.invalid[from..to]
+                .iter()
+                .map(|&(text_end, raw_end)| (text_end - start, raw_end - raw_start))
+                .collect(),
+        }
+    }
+
+    /// The index of the line the byte at `offset` is on.
+    fn line_of(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// Where the line ends, without its line terminator.
+    fn content_end(&self, index: usize) -> usize {
+        let end = self
+            .starts
+            .get(index + 1)
+            .map_or(self.text.len(), |&next| next);
+        let line = &self.text[..end];
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        line.strip_suffix('\r').unwrap_or(line).len()
+    }
+
+    /// The first and last lines of a span. A span that takes in the line
+    /// terminator of its last line reaches the next one too, since replacing
+    /// it joins them.
+    fn of(&self, span: &Span) -> (usize, usize) {
+        let first = self.line_of(span.start);
+        let mut last = self.line_of(span.end.saturating_sub(1).max(span.start));
+        if span.end > self.content_end(last) && last + 1 < self.starts.len() {
+            last += 1;
+        }
+        (first, last)
+    }
+
+    fn context_line(&self, index: usize) -> ContextLine {
+        let start = self.starts[index];
+        ContextLine {
+            line_number: index + 1,
+            line_offset: self.raw_offset(start) as u64,
+            line: self.text[start..self.content_end(index)].to_string(),
+        }
+    }
+}
+
+/// Whether the file looks binary. One skipped only because it isn't valid
+/// UTF-8 is most likely text in another encoding, like Latin-1, so unlike one
+/// with a NUL byte it isn't skipped silently.
+fn check_binary(bytes: &[u8], path: &Path, config: &Config, state: &SearchState) -> bool {
+    let binary = is_binary(bytes);
+    let block = &bytes[..bytes.len().min(BINARY_DETECTION_BLOCK)];
+    if binary && config.binary_mode == BinaryMode::Skip && !block.contains(&0) {
+        state.warn(ConfigError::SkippedNotUtf8(
+            path.to_string_lossy().into_owned(),
+        ));
+    }
+    binary
+}
+
+/// A file is considered binary if its first block contains a NUL byte or
+/// isn't valid UTF-8. A multi-byte character cut off by the end of the block
+/// doesn't count as invalid.
+fn is_binary(bytes: &[u8]) -> bool {
+    let block = &bytes[..bytes.len().min(BINARY_DETECTION_BLOCK)];
+
+    if block.contains(&0) {
+        return true;
+    }
+
+    match std::str::from_utf8(block) {
+        Ok(_) => false,
+        Err(err) => err.error_len().is_some(),
+    }
+}
+
+fn not_utf8_from_line(path: &Path, line_number: usize) -> ConfigError {
+    ConfigError::NotUtf8FromLine(path.to_string_lossy().into_owned(), line_number)
+}
+
+fn read_error(path: &Path, err: impl ToString) -> ConfigError {
+    ConfigError::FileReadError(path.to_string_lossy().into_owned(), err.to_string())
+}
+
+/// The part shared by the buffered and the memory-mapped readers: gets fed
+/// one raw line at a time and collects the occurrences found in it.
+struct LineSearcher<'a> {
+    path: &'a Path,
+    config: &'a Config,
+    binary: bool,
+    line_number: usize,
+    /// Byte offset of the start of the next line.
+    offset: u64,
+    occurrences: Vec<Occurrence>,
+    context: Vec<ContextLine>,
+    /// The last lines that weren't kept, in case an occurrence shows up and
+    /// they turn out to be its before context.
+    before: VecDeque<ContextLine>,
+    /// How many of the next lines are still after context.
+    after_left: usize,
+}
+
+impl<'a> LineSearcher<'a> {
+    fn new(path: &'a Path, config: &'a Config, binary: bool) -> LineSearcher<'a> {
+        LineSearcher {
+            path,
+            config,
+            binary,
+            line_number: 0,
+            offset: 0,
+            occurrences: Vec::new(),
+            context: Vec::new(),
+            before: VecDeque::with_capacity(config.before_context),
+            after_left: 0,
+        }
+    }
+
+    fn skips_file(&self) -> bool {
+        self.binary && self.config.binary_mode == BinaryMode::Skip
+    }
+
+    /// Returns false once there's no point in reading any more lines.
+    fn line(&mut self, raw: &[u8], state: &SearchState) -> Result<bool, ConfigError> {
+        if let Some(max) = self.config.max_lines_per_file {
+            if self.line_number >= max {
+                return Ok(false);
+            }
+        }
+        if !state.take_line() {
+            return Ok(false);
+        }
+        self.line_number += 1;
+        let line_offset = self.offset;
+        self.offset += raw.len() as u64;
+
+        let raw = match raw.strip_suffix(b"\n") {
+            Some(raw) => raw.strip_suffix(b"\r").unwrap_or(raw),
+            None => raw,
+        };
+
+        let line = if self.binary || self.config.binary_mode == BinaryMode::Text {
+            String::from_utf8_lossy(raw).into_owned()
+        } else {
+            match std::str::from_utf8(raw) {
+                Ok(line) => line.to_string(),
+                // Invalid UTF-8 past the block binary files are detected
+                // with: the file is binary from this line on.
+                Err(_) => {
+                    self.binary = true;
+                    if self.config.binary_mode == BinaryMode::Skip {
+                        state.warn(not_utf8_from_line(self.path, self.line_number));
+                        return Ok(false);
+                    }
+                    String::from_utf8_lossy(raw).into_owned()
+                }
+            }
+        };
+
+        let mut spans = if self.matches_done(state) {
+            Vec::new()
+        } else if self.config.invert {
+            // An inverted "occurrence" is the whole line, with nothing in it
+            // to highlight.
+            if self.config.matcher.find_spans(&line).is_empty() {
+                vec![Span {
+                    start: 0,
+                    end: 0,
+                    pattern: 0,
+                    groups: Vec::new(),
+                }]
+            } else {
+                Vec::new()
+            }
+        } else {
+            self.config.matcher.find_spans(&line)
+        };
+        if let Some(max) = self.config.max_count {
+            spans.truncate(max - self.occurrences.len());
+        }
+        spans.truncate(state.take_matches(spans.len()));
+
+        if spans.is_empty() {
+            self.push_context(line_offset, line);
+        } else {
+            self.context.extend(self.before.drain(..));
+            self.after_left = self.config.after_context;
+
+            let line = Arc::new(LineText::decoded(line, raw));
+            for span in spans {
+                self.occurrences.push(Occurrence {
+                    line_number: self.line_number,
+                    last_line_number: self.line_number,
+                    line_offset,
+                    line: line.clone(),
+                    start: span.start,
+                    end: span.end,
+                    pattern: span.pattern,
+                    groups: span.groups,
+                });
+            }
+        }
+
+        let reported_only = self.config.quiet
+            || self.config.list_files.is_some()
+            || (self.binary && self.config.binary_mode == BinaryMode::Report);
+        if reported_only && !self.occurrences.is_empty() {
+            // Only whether the file matches matters, not where.
+            return Ok(false);
+        }
+
+        // Once no more matches can be taken, keep reading only for the
+        // after context of the last one.
+        let wants_more = !self.matches_done(state) || self.after_left > 0;
+        Ok(wants_more && !state.out_of_lines() && !state.out_of_files())
+    }
+
+    /// True once `-m` or `--max-total` won't let any more occurrences in.
+    fn matches_done(&self, state: &SearchState) -> bool {
+        let file_done = match self.config.max_count {
+            Some(max) => self.occurrences.len() >= max,
+            None => false,
+        };
+        file_done || state.out_of_matches()
+    }
+
+    /// Keeps a line without occurrences if it's after context, or remembers
+    /// it in case it's before context of a later occurrence.
+    fn push_context(&mut self, line_offset: u64, line: String) {
+        let context_line = ContextLine {
+            line_number: self.line_number,
+            line_offset,
+            line,
+        };
+
+        if self.after_left > 0 {
+            self.after_left -= 1;
+            self.context.push(context_line);
+        } else if self.config.before_context > 0 {
+            if self.before.len() == self.config.before_context {
+                self.before.pop_front();
+            }
+            self.before.push_back(context_line);
+        }
+    }
+
+    fn finish(self) -> FileResult {
+        FileResult {
+            binary: self.binary,
+            lines_searched: self.line_number,
+            occurrences: self.occurrences,
+            context: self.context,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(bytes: &[u8]) -> LineText {
+        LineText::decoded(String::from_utf8_lossy(bytes).into_owned(), bytes)
+    }
+
+    #[test]
+    fn valid_utf8_keeps_its_offsets() {
+        let line = decoded("é \u{FFFD} x".as_bytes());
+        assert!(line.invalid.is_empty());
+        assert_eq!(line.raw_offset(7), 7);
+    }
+
+    #[test]
+    fn an_invalid_byte_counts_as_one_byte() {
+        let line = decoded(b"id \xff more");
+        assert_eq!(&*line, "id \u{FFFD} more");
+        assert_eq!(line.invalid, [(6, 4)]);
+        assert_eq!(line.raw_offset(3), 3);
+        assert_eq!(line.raw_offset(7), 5);
+    }
+
+    #[test]
+    fn every_invalid_sequence_has_its_own_replacement() {
+        // Two bytes that can't start a character, then a truncated one.
+        let line = decoded(b"x\xfe\xfdy\xe2\x82z");
+        assert_eq!(&*line, "x\u{FFFD}\u{FFFD}y\u{FFFD}z");
+        assert_eq!(
+            invalid_utf8(b"x\xfe\xfdy\xe2\x82z"),
+            [(4, 2), (7, 3), (11, 6)]
+        );
+        assert_eq!(line.raw_offset(7), 3);
+        assert_eq!(line.raw_offset(11), 6);
+    }
+
+    #[test]
+    fn a_block_of_a_lossy_file_knows_where_it_is() {
+        let bytes = b"a\xff\nb\xffc\n";
+        let text = String::from_utf8_lossy(bytes);
+        let invalid = invalid_utf8(bytes);
+        let lines = Lines::new(&text, &invalid);
+
+        assert_eq!(lines.starts, [0, 5]);
+        assert_eq!(lines.raw_offset(5), 3);
+        let block = lines.text(5, 10);
+        assert_eq!(&*block, "b\u{FFFD}c");
+        assert_eq!(block.raw_offset(4), 2);
+        assert_eq!(lines.context_line(1).line_offset, 3);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::ConfigError;
+use colored::*;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The file types known out of the box, matched against file names only.
+const DEFAULT_TYPES: &[(&str, &[&str])] = &[
+    ("c", &["*.c", "*.h"]),
+    ("cargo", &["Cargo.toml", "Cargo.lock"]),
+    ("cmake", &["CMakeLists.txt", "*.cmake"]),
+    (
+        "cpp",
+        &["*.cpp", "*.cc", "*.cxx", "*.hpp", "*.hh", "*.hxx", "*.h"],
+    ),
+    ("cs", &["*.cs"]),
+    ("css", &["*.css", "*.scss", "*.sass", "*.less"]),
+    ("csv", &["*.csv", "*.tsv"]),
+    ("docker", &["Dockerfile", "*.dockerfile", "Dockerfile.*"]),
+    ("go", &["*.go", "go.mod", "go.sum"]),
+    ("html", &["*.html", "*.htm"]),
+    ("java", &["*.java"]),
+    ("js", &["*.js", "*.mjs", "*.cjs", "*.jsx"]),
+    ("json", &["*.json", "*.jsonl"]),
+    ("kotlin", &["*.kt", "*.kts"]),
+    ("log", &["*.log"]),
+    ("lua", &["*.lua"]),
+    (
+        "make",
+        &["Makefile", "makefile", "GNUmakefile", "*.mk", "*.mak"],
+    ),
+    ("markdown", &["*.md", "*.markdown"]),
+    ("php", &["*.php"]),
+    (
+        "py",
+        &["*.py", "*.pyi", "pyproject.toml", "requirements.txt"],
+    ),
+    ("rb", &["*.rb", "Gemfile", "Rakefile"]),
+    ("rust", &["*.rs"]),
+    ("sh", &["*.sh", "*.bash", "*.zsh", ".bashrc", ".zshrc"]),
+    ("sql", &["*.sql"]),
+    ("swift", &["*.swift"]),
+    ("toml", &["*.toml", "Cargo.lock"]),
+    ("ts", &["*.ts", "*.tsx", "*.mts", "*.cts"]),
+    ("txt", &["*.txt"]),
+    ("xml", &["*.xml", "*.xsd", "*.xsl"]),
+    ("yaml", &["*.yaml", "*.yml"]),
+];
+
+/// The `--type` and `--type-not` selections, along with every type that can
+/// be selected (the default ones plus those added with `--type-add`).
+#[derive(Debug)]
+pub struct FileTypes {
+    definitions: BTreeMap<String, Vec<String>>,
+    selected: Vec<String>,
+    negated: Vec<String>,
+    select: Option<GlobSet>,
+    negate: Option<GlobSet>,
+}
+
+impl Default for FileTypes {
+    fn default() -> FileTypes {
+        let definitions = DEFAULT_TYPES
+            .iter()
+            .map(|(name, globs)| {
+                let globs = globs.iter().map(|glob| glob.to_string()).collect();
+                (name.to_string(), globs)
+            })
+            .collect();
+
+        FileTypes {
+            definitions,
+            selected: Vec::new(),
+            negated: Vec::new(),
+            select: None,
+            negate: None,
+        }
+    }
+}
+
+impl FileTypes {
+    /// Adds a glob to a type from a `name:glob` definition, creating the type
+    /// if it doesn't exist yet.
+    pub fn add(&mut self, definition: &str) -> Result<(), ConfigError> {
+        match definition.split_once(':') {
+            Some((name, glob)) if !name.is_empty() && !glob.is_empty() => {
+                self.definitions
+                    .entry(name.to_string())
+                    .or_default()
+                    .push(glob.to_string());
+                Ok(())
+            }
+            _ => Err(ConfigError::InvalidTypeDefinition(definition.to_string())),
+        }
+    }
+
+    pub fn select(&mut self, name: &str) {
+        self.selected.push(name.to_string());
+    }
+
+    pub fn negate(&mut self, name: &str) {
+        self.negated.push(name.to_string());
+    }
+
+    /// Compiles the selected types. Has to be called once all the arguments
+    /// are parsed, since `--type-add` may come after the `--type` using it.
+    pub fn build(&mut self) -> Result<(), ConfigError> {
+        self.select = self.glob_set(&self.selected)?;
+        self.negate = self.glob_set(&self.negated)?;
+        Ok(())
+    }
+
+    fn glob_set(&self, names: &[String]) -> Result<Option<GlobSet>, ConfigError> {
+        if names.is_empty() {
+            return Ok(None);
+        }
+
+        let mut builder = GlobSetBuilder::new();
+        for name in names {
+            let globs = match self.definitions.get(name) {
+                Some(globs) => globs,
+                None => return Err(ConfigError::UnknownFileType(name.clone())),
+            };
+
+            for glob in globs {
+                let glob = GlobBuilder::new(glob)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|err| {
+                        ConfigError::InvalidGlob(glob.clone(), err.kind().to_string())
+                    })?;
+                builder.add(glob);
+            }
+        }
+
+        builder
+            .build()
+            .map(Some)
+            .map_err(|err| ConfigError::InvalidGlob(names.join(","), err.to_string()))
+    }
+
+    /// Whether a file is left out by the selected types. Only its name is
+    /// looked at.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        let file_name = match path.file_name() {
+            Some(file_name) => file_name,
+            None => return false,
+        };
+
+        if let Some(negate) = &self.negate {
+            if negate.is_match(file_name) {
+                return true;
+            }
+        }
+
+        match &self.select {
+            Some(select) => !select.is_match(file_name),
+            None => false,
+        }
+    }
+
+    pub fn print_list(&self) {
+        for (name, globs) in &self.definitions {
+            println!("{}: {}", name.green().bold(), globs.join(", "));
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::ignore::IgnoreStack;
+use crate::{printer, searcher, Config, ConfigError, SearchState};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+enum Work {
+    /// A folder, with the ignore files of the folder it's in.
+    Dir(PathBuf, Arc<IgnoreStack>),
+    File(PathBuf),
+}
+
+/// Directories still to be listed and files still to be searched, shared by
+/// all the workers. `active` counts the workers busy with an item, since they
+/// may still push new ones: the walk is only over once it's 0 and the queue
+/// is empty.
+struct WorkQueue {
+    inner: Mutex<QueueInner>,
+    available: Condvar,
+}
+
+struct QueueInner {
+    items: Vec<Work>,
+    active: usize,
+}
+
+impl WorkQueue {
+    fn new(items: Vec<Work>) -> WorkQueue {
+        WorkQueue {
+            inner: Mutex::new(QueueInner { items, active: 0 }),
+            available: Condvar::new(),
+        }
+    }
+
+    fn pop(&self) -> Option<Work> {
+        let mut inner = self.inner.lock().unwrap();
+        loop {
+            if let Some(work) = inner.items.pop() {
+                inner.active += 1;
+                return Some(work);
+            }
+            if inner.active == 0 {
+                return None;
+            }
+            inner = self.available.wait(inner).unwrap();
+        }
+    }
+
+    fn push(&self, items: Vec<Work>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.items.extend(items);
+        self.available.notify_all();
+    }
+
+    fn done(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.active -= 1;
+        if inner.active == 0 && inner.items.is_empty() {
+            self.available.notify_all();
+        }
+    }
+}
+
+/// Marks an item popped off the queue as done once dropped, even when the
+/// worker panics on it, so that the other workers don't wait for it forever.
+struct Popped<'a>(&'a WorkQueue);
+
+impl Drop for Popped<'_> {
+    fn drop(&mut self) {
+        self.0.done();
+    }
+}
+
+/// Walks `dir` with `config.threads` workers, each of them both listing
+/// directories and searching files. Only a problem with `dir` itself is an
+/// error, anything below it ends up in `state.warnings`.
+pub fn folder_iterator(
+    dir: &Path,
+    config: &Config,
+    state: &SearchState,
+) -> Result<(), ConfigError> {
+    if !dir.is_dir() {
+        let invalid_path = dir.to_string_lossy().into_owned();
+        return Err(ConfigError::IsNotAFolder(invalid_path));
+    }
+
+    let mut warnings = Vec::new();
+    let ignore = IgnoreStack::root(dir, &config.ignore, &mut warnings);
+    for warning in warnings {
+        state.warn(warning);
+    }
+
+    let items = read_folder(dir, &ignore, config, state)?;
+    let queue = WorkQueue::new(items);
+
+    thread::scope(|scope| {
+        for _ in 0..config.threads {
+            scope.spawn(|| worker(&queue, config, state));
+        }
+    });
+
+    Ok(())
+}
+
+fn worker(queue: &WorkQueue, config: &Config, state: &SearchState) {
+    while let Some(work) = queue.pop() {
+        let _popped = Popped(queue);
+        if state.should_stop() {
+            // Drain the queue without doing anything, the search is over.
+            continue;
+        }
+
+        match work {
+            Work::Dir(path, parent_ignore) => {
+                let mut warnings = Vec::new();
+                let ignore =
+                    IgnoreStack::child(&parent_ignore, &path, &config.ignore, &mut warnings);
+                for warning in warnings {
+                    state.warn(warning);
+                }
+
+                match read_folder(&path, &ignore, config, state) {
+                    Ok(items) => queue.push(items),
+                    Err(err) => state.warn(err),
+                }
+            }
+            Work::File(path) => search_file(&path, config, state),
+        }
+    }
+}
+
+fn search_file(path: &Path, config: &Config, state: &SearchState) {
+    match searcher::finds(path, config, state) {
+        Ok(result) => {
+            state.searched();
+            printer::print_file_result(path, result, config, state);
+        }
+        Err(err) => state.warn(err),
+    }
+}
+
+/// Lists a folder, leaving out what its ignore files exclude. The entries are
+/// returned in reverse so that popping them off the queue visits them in the
+/// order `read_dir` gave them.
+fn read_folder(
+    dir: &Path,
+    ignore: &Arc<IgnoreStack>,
+    config: &Config,
+    state: &SearchState,
+) -> Result<Vec<Work>, ConfigError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            let error_dir = dir.to_string_lossy().into_owned();
+            return Err(ConfigError::ReadDirError(format!("{}: {}", error_dir, err)));
+        }
+    };
+
+    let mut items = Vec::new();
+    for entry in entries {
+        match entry {
+            Ok(entry) => match entry.file_type() {
+                Ok(file_type) => {
+                    let path = entry.path();
+                    let is_dir = file_type.is_dir();
+                    if ignore.is_ignored(&path, is_dir) || is_excluded(&path, is_dir, config) {
+                        continue;
+                    }
+
+                    if is_dir {
+                        items.push(Work::Dir(path, Arc::clone(ignore)));
+                    } else if file_type.is_file() {
+                        items.push(Work::File(path));
+                    }
+                }
+                Err(err) => state.warn(ConfigError::EntryProcessingError(format!(
+                    "{}: {}",
+                    entry.path().to_string_lossy(),
+                    err
+                ))),
+            },
+            Err(err) => state.warn(ConfigError::EntryProcessingError(err.to_string())),
+        }
+    }
+
+    items.reverse();
+    Ok(items)
+}
+
+/// Checks the `--glob` patterns against the path relative to the searched
+/// folder, then the selected file types against the file name.
+fn is_excluded(path: &Path, is_dir: bool, config: &Config) -> bool {
+    let relative = path.strip_prefix(&config.folder_name).unwrap_or(path);
+    if config.globs.is_excluded(relative, is_dir) {
+        return true;
+    }
+
+    !is_dir && config.types.is_excluded(path)
+}